@@ -11,6 +11,13 @@ static BLACKLIST: [&str; 6] = [
 ];
 
 fn main() -> Result<(), Box<dyn Error>> {
+    println!("cargo:rerun-if-changed=build.rs");
+
+    // Host builds are only for testing the library, which doesn't touch ffmpeg
+    if env::var("CARGO_CFG_TARGET_VENDOR").as_deref() != Ok("vex") {
+        return Ok(());
+    }
+
     let libdir_path = PathBuf::from("native_libs")
         .canonicalize()
         .expect("cannot canonicalize path");
@@ -41,8 +48,6 @@ fn main() -> Result<(), Box<dyn Error>> {
     println!("cargo:rustc-link-lib=static=swscale");
     println!("cargo:rustc-link-lib=static=dav1d");
 
-    println!("cargo:rerun-if-changed=build.rs");
-
     // Search include dir and bindgen all headers
     let include_dir = libdir_path.join("include");
 
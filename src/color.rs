@@ -0,0 +1,272 @@
+//! Y'CbCr -> RGB conversion
+//!
+//! All math is Q13 fixed point with i32 accumulators, so the NEON path (widening multiplies,
+//! `vqrshrun`) and the scalar reference produce identical bytes.
+
+/// Y'CbCr matrix, i.e. which luma weights the source was encoded with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Matrix {
+    /// SD content
+    Bt601,
+    /// HD content
+    Bt709,
+    /// UHD/HDR content, non-constant luminance
+    Bt2020,
+}
+
+impl Matrix {
+    /// Best guess for untagged content, same heuristic most players use
+    pub fn default_for_height(height: usize) -> Self {
+        if height <= 576 {
+            Self::Bt601
+        } else {
+            Self::Bt709
+        }
+    }
+
+    /// `(Kr, Kb)` luma weights
+    const fn weights(self) -> (f64, f64) {
+        match self {
+            Self::Bt601 => (0.299, 0.114),
+            Self::Bt709 => (0.2126, 0.0722),
+            Self::Bt2020 => (0.2627, 0.0593),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Range {
+    /// Y' in 16..=235, CbCr in 16..=240 ("TV"/MPEG range)
+    #[default]
+    Limited,
+    /// Everything in 0..=255 ("PC"/JPEG range)
+    Full,
+}
+
+const FRAC_BITS: i32 = 13;
+
+const fn q13(value: f64) -> i16 {
+    let scaled = value * (1 << FRAC_BITS) as f64;
+    if scaled < 0.0 {
+        (scaled - 0.5) as i16
+    } else {
+        (scaled + 0.5) as i16
+    }
+}
+
+/// Q13 coefficients for one matrix/range combination
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Conversion {
+    y_offset: i16,
+    y_scale: i16,
+    cr_to_r: i16,
+    cb_to_g: i16,
+    cr_to_g: i16,
+    cb_to_b: i16,
+}
+
+impl Conversion {
+    pub const fn new(matrix: Matrix, range: Range) -> Self {
+        let (kr, kb) = matrix.weights();
+        let kg = 1.0 - kr - kb;
+
+        let (y_offset, y_scale, c_scale) = match range {
+            Range::Limited => (16, 255.0 / 219.0, 255.0 / 224.0),
+            Range::Full => (0, 1.0, 1.0),
+        };
+
+        Self {
+            y_offset,
+            y_scale: q13(y_scale),
+            cr_to_r: q13(2.0 * (1.0 - kr) * c_scale),
+            cb_to_g: q13(-2.0 * (1.0 - kb) * kb / kg * c_scale),
+            cr_to_g: q13(-2.0 * (1.0 - kr) * kr / kg * c_scale),
+            cb_to_b: q13(2.0 * (1.0 - kb) * c_scale),
+        }
+    }
+
+    /// Reference conversion of a single pixel to `(r, g, b)`
+    pub fn convert_pixel(&self, y: u8, cb: u8, cr: u8) -> (u8, u8, u8) {
+        let luma = (y as i32 - self.y_offset as i32) * self.y_scale as i32;
+        let cb = cb as i32 - 128;
+        let cr = cr as i32 - 128;
+
+        let narrow = |acc: i32| ((acc + (1 << (FRAC_BITS - 1))) >> FRAC_BITS).clamp(0, 255) as u8;
+        (
+            narrow(luma + cr * self.cr_to_r as i32),
+            narrow(luma + cb * self.cb_to_g as i32 + cr * self.cr_to_g as i32),
+            narrow(luma + cb * self.cb_to_b as i32),
+        )
+    }
+
+    /// Converts 8 pixels at once to planar `(b, g, r)`, matching [`Self::convert_pixel`] exactly
+    ///
+    /// # Safety
+    /// Needs NEON, which the Brain always has
+    #[cfg(target_arch = "arm")]
+    pub unsafe fn convert_neon(
+        &self,
+        luma: core::arch::arm::uint8x8_t,
+        chroma_blue: core::arch::arm::uint8x8_t,
+        chroma_red: core::arch::arm::uint8x8_t,
+    ) -> [core::arch::arm::uint8x8_t; 3] {
+        use core::arch::arm::*;
+
+        unsafe {
+            let half = vdupq_n_s16(128);
+
+            // Y' - offset can go negative for limited range footroom, so widen as signed
+            let luma = vreinterpretq_s16_u16(vsubl_u8(luma, vdup_n_u8(self.y_offset as u8)));
+            let chroma_blue = vsubq_s16(vreinterpretq_s16_u16(vmovl_u8(chroma_blue)), half);
+            let chroma_red = vsubq_s16(vreinterpretq_s16_u16(vmovl_u8(chroma_red)), half);
+
+            let luma = [
+                vmull_n_s16(vget_low_s16(luma), self.y_scale),
+                vmull_n_s16(vget_high_s16(luma), self.y_scale),
+            ];
+
+            // acc + a * ka (+ b * kb), narrowed back to u8 with rounding & saturation
+            let channel = |a: int16x8_t, ka: i16, b: int16x8_t, kb: i16| {
+                let low = vmlal_n_s16(
+                    vmlal_n_s16(luma[0], vget_low_s16(a), ka),
+                    vget_low_s16(b),
+                    kb,
+                );
+                let high = vmlal_n_s16(
+                    vmlal_n_s16(luma[1], vget_high_s16(a), ka),
+                    vget_high_s16(b),
+                    kb,
+                );
+                vqmovn_u16(vcombine_u16(
+                    vqrshrun_n_s32::<FRAC_BITS>(low),
+                    vqrshrun_n_s32::<FRAC_BITS>(high),
+                ))
+            };
+
+            [
+                channel(chroma_blue, self.cb_to_b, chroma_red, 0),
+                channel(chroma_blue, self.cb_to_g, chroma_red, self.cr_to_g),
+                channel(chroma_red, self.cr_to_r, chroma_blue, 0),
+            ]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Pixels = [((u8, u8, u8), (u8, u8, u8)); 5];
+
+    /// `(matrix, range, [(y, cb, cr) -> (r, g, b)])` for black, white & the primaries. Colour
+    /// codes are each matrix's own encoding of pure red, green & blue, so the odd 1 off is
+    /// rounding in the codes rather than the conversion
+    #[rustfmt::skip]
+    const GOLDEN: [(Matrix, Range, Pixels); 6] = [
+        (Matrix::Bt601, Range::Limited, [
+            ((16, 128, 128), (0, 0, 0)),
+            ((235, 128, 128), (255, 255, 255)),
+            ((81, 90, 240), (254, 0, 0)),
+            ((145, 54, 34), (0, 255, 1)),
+            ((41, 240, 110), (0, 0, 255)),
+        ]),
+        (Matrix::Bt601, Range::Full, [
+            ((0, 128, 128), (0, 0, 0)),
+            ((255, 128, 128), (255, 255, 255)),
+            ((76, 85, 255), (254, 0, 0)),
+            ((150, 44, 21), (0, 255, 1)),
+            ((29, 255, 107), (0, 0, 254)),
+        ]),
+        (Matrix::Bt709, Range::Limited, [
+            ((16, 128, 128), (0, 0, 0)),
+            ((235, 128, 128), (255, 255, 255)),
+            ((63, 102, 240), (255, 1, 0)),
+            ((173, 42, 26), (0, 255, 1)),
+            ((32, 240, 118), (1, 0, 255)),
+        ]),
+        (Matrix::Bt709, Range::Full, [
+            ((0, 128, 128), (0, 0, 0)),
+            ((255, 128, 128), (255, 255, 255)),
+            ((54, 99, 255), (254, 0, 0)),
+            ((182, 30, 12), (0, 255, 0)),
+            ((18, 255, 116), (0, 0, 254)),
+        ]),
+        (Matrix::Bt2020, Range::Limited, [
+            ((16, 128, 128), (0, 0, 0)),
+            ((235, 128, 128), (255, 255, 255)),
+            ((74, 97, 240), (255, 1, 1)),
+            ((164, 47, 25), (0, 255, 0)),
+            ((29, 240, 119), (0, 0, 255)),
+        ]),
+        (Matrix::Bt2020, Range::Full, [
+            ((0, 128, 128), (0, 0, 0)),
+            ((255, 128, 128), (255, 255, 255)),
+            ((67, 92, 255), (254, 0, 0)),
+            ((173, 36, 11), (0, 255, 0)),
+            ((15, 255, 118), (0, 0, 254)),
+        ]),
+    ];
+
+    #[test]
+    fn golden_values() {
+        for (matrix, range, pixels) in GOLDEN {
+            let conversion = Conversion::new(matrix, range);
+            for ((y, cb, cr), rgb) in pixels {
+                assert_eq!(
+                    conversion.convert_pixel(y, cb, cr),
+                    rgb,
+                    "{matrix:?} {range:?} ({y}, {cb}, {cr})"
+                );
+            }
+        }
+    }
+
+    /// Straight from the matrix definition, in floating point
+    fn reference(matrix: Matrix, range: Range, y: u8, cb: u8, cr: u8) -> [f64; 3] {
+        let (kr, kb) = matrix.weights();
+        let kg = 1.0 - kr - kb;
+        let (y_offset, y_scale, c_scale) = match range {
+            Range::Limited => (16.0, 255.0 / 219.0, 255.0 / 224.0),
+            Range::Full => (0.0, 1.0, 1.0),
+        };
+
+        let luma = (y as f64 - y_offset) * y_scale;
+        let cb = (cb as f64 - 128.0) * c_scale;
+        let cr = (cr as f64 - 128.0) * c_scale;
+        let r = luma + 2.0 * (1.0 - kr) * cr;
+        let b = luma + 2.0 * (1.0 - kb) * cb;
+        let g = (luma - kr * r - kb * b) / kg;
+        [r, g, b].map(|channel| channel.clamp(0.0, 255.0))
+    }
+
+    #[test]
+    fn within_one_of_reference() {
+        let matrices = [Matrix::Bt601, Matrix::Bt709, Matrix::Bt2020];
+        for matrix in matrices {
+            for range in [Range::Limited, Range::Full] {
+                let conversion = Conversion::new(matrix, range);
+                for y in (0..=255).step_by(5) {
+                    for cb in (0..=255).step_by(15) {
+                        for cr in (0..=255).step_by(15) {
+                            let (r, g, b) = conversion.convert_pixel(y, cb, cr);
+                            let expected = reference(matrix, range, y, cb, cr);
+                            for (channel, expected) in [r, g, b].into_iter().zip(expected) {
+                                assert!(
+                                    (channel as f64 - expected).abs() <= 1.0,
+                                    "{matrix:?} {range:?} ({y}, {cb}, {cr}): {channel} vs {expected}"
+                                );
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn default_matrix() {
+        assert_eq!(Matrix::default_for_height(480), Matrix::Bt601);
+        assert_eq!(Matrix::default_for_height(576), Matrix::Bt601);
+        assert_eq!(Matrix::default_for_height(720), Matrix::Bt709);
+    }
+}
@@ -0,0 +1,866 @@
+//! `player.toml` on the SD card
+//!
+//! INI-style subset of TOML: `[section]` headers, `key = value` pairs and `#`/`;` comments. Values
+//! are quoted strings, bare words, integers or booleans. Everything is optional.
+//!
+//! ```toml
+//! [playback]
+//! file = "rickroll.webm"   # skip the menu, play this (or an .m3u) straight away
+//! dir = "videos"           # where the menu looks
+//! loop = "off"             # off | file | all
+//! segment = "0:05-0:30"    # loop just this part of every file (A-B repeat)
+//! shuffle = false
+//! keyframe_index = false   # index keyframes for fast, accurate seeks (cached as <file>.idx)
+//!
+//! [video]
+//! fit = "fit"              # fit | fill | stretch | original
+//! scaler = "bilinear"      # nearest | bilinear | area
+//! matrix = "auto"          # auto | bt601 | bt709 | bt2020
+//! range = "auto"           # auto | limited | full
+//! track = "auto"           # auto, a video track number (0 is the first) or a track title
+//!
+//! [log]
+//! level = "info"           # quiet | error | warning | info | verbose | debug
+//! format_debug = false     # every AVFormatContext debug flag
+//! alloc_report = false     # write ffmpeg's live allocations to alloc_report.txt after each file
+//! alloc_benchmark = false  # time ffmpeg's malloc/free on startup
+//!
+//! [io]
+//! buffer_size = 65536      # AVIO buffer, bytes
+//! probe_size = 5000000     # bytes avformat may read to detect streams
+//! queue_depth = 4          # frames decoded ahead, counting the one on screen; at least 2
+//! queue_memory = 2097152   # bytes those frames may take up, which can lower queue_depth
+//! memory_budget = 33554432 # bytes ffmpeg may allocate; 3/4 of the heap if left out
+//! ```
+
+use alloc::{
+    borrow::ToOwned,
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::{fmt, time::Duration};
+
+use crate::{
+    color::{Matrix, Range},
+    layout::FitMode,
+    scale::ScaleFilter,
+};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum LoopMode {
+    #[default]
+    Off,
+    /// Replay the same file forever
+    File,
+    /// Start the playlist over after the last file
+    All,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum LogLevel {
+    Quiet,
+    Error,
+    Warning,
+    #[default]
+    Info,
+    Verbose,
+    Debug,
+}
+
+/// Which video track of a file to play
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub enum Track {
+    /// Whatever ffmpeg thinks is the main one
+    #[default]
+    Best,
+    /// Counting video tracks only, from 0
+    Index(usize),
+    /// Track whose `title` tag matches, ignoring case
+    Title(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Video or `.m3u` to play on startup instead of showing the menu
+    pub file: Option<String>,
+    /// Directory the menu lists, `""` being the root of the SD card
+    pub dir: String,
+    pub loop_mode: LoopMode,
+    /// A-B repeat, overriding `loop_mode` for each file
+    pub segment: Option<(Duration, Duration)>,
+    pub shuffle: bool,
+    /// Build (or load) a keyframe index when opening a file
+    pub keyframe_index: bool,
+    pub fit_mode: FitMode,
+    pub scale_filter: ScaleFilter,
+    /// Overrides whatever the video is tagged with
+    pub color_matrix: Option<Matrix>,
+    pub color_range: Option<Range>,
+    pub track: Track,
+    pub log_level: LogLevel,
+    pub format_debug: bool,
+    /// Write the allocation report to the SD card after each file
+    pub alloc_report: bool,
+    pub alloc_benchmark: bool,
+    pub buffer_size: usize,
+    pub probe_size: Option<i64>,
+    /// Converted frames to keep around, including the one on screen
+    pub queue_depth: usize,
+    /// Upper bound on the memory those take, in bytes
+    pub queue_memory: usize,
+    /// Cap on ffmpeg's allocations, in bytes; `None` to size it from the heap
+    pub memory_budget: Option<usize>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            file: None,
+            dir: String::new(),
+            loop_mode: LoopMode::Off,
+            segment: None,
+            shuffle: false,
+            keyframe_index: false,
+            fit_mode: FitMode::default(),
+            scale_filter: ScaleFilter::default(),
+            color_matrix: None,
+            color_range: None,
+            track: Track::Best,
+            log_level: LogLevel::default(),
+            format_debug: false,
+            alloc_report: false,
+            alloc_benchmark: false,
+            buffer_size: 64 * 1024,
+            probe_size: None,
+            queue_depth: 4,
+            queue_memory: 2 * 1024 * 1024,
+            memory_budget: None,
+        }
+    }
+}
+
+/// Every section & the keys it accepts
+const SECTIONS: &[(&str, &[&str])] = &[
+    (
+        "playback",
+        &[
+            "file",
+            "dir",
+            "loop",
+            "segment",
+            "shuffle",
+            "keyframe_index",
+        ],
+    ),
+    ("video", &["fit", "scaler", "matrix", "range", "track"]),
+    (
+        "log",
+        &["level", "format_debug", "alloc_report", "alloc_benchmark"],
+    ),
+    (
+        "io",
+        &[
+            "buffer_size",
+            "probe_size",
+            "queue_depth",
+            "queue_memory",
+            "memory_budget",
+        ],
+    ),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigError {
+    /// 1-based
+    pub line: usize,
+    pub kind: ConfigErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigErrorKind {
+    Syntax(&'static str),
+    UnknownSection(String),
+    /// `key = value` before any `[section]`
+    NoSection(String),
+    UnknownKey {
+        section: &'static str,
+        key: String,
+    },
+    InvalidValue {
+        key: String,
+        expected: String,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: ", self.line)?;
+        match &self.kind {
+            ConfigErrorKind::Syntax(message) => write!(f, "{message}"),
+            ConfigErrorKind::UnknownSection(name) => {
+                let names: Vec<&str> = SECTIONS.iter().map(|(name, _)| *name).collect();
+                write!(
+                    f,
+                    "unknown section [{name}], expected one of {}",
+                    names.join(", ")
+                )
+            }
+            ConfigErrorKind::NoSection(key) => {
+                write!(f, "`{key}` needs to be inside a [section]")
+            }
+            ConfigErrorKind::UnknownKey { section, key } => {
+                let keys = SECTIONS
+                    .iter()
+                    .find(|(name, _)| name == section)
+                    .map_or(&[][..], |(_, keys)| keys);
+                write!(
+                    f,
+                    "unknown key `{key}` in [{section}], expected one of {}",
+                    keys.join(", ")
+                )
+            }
+            ConfigErrorKind::InvalidValue { key, expected } => {
+                write!(f, "bad value for `{key}`, expected {expected}")
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Value {
+    String(String),
+    Integer(i64),
+    Bool(bool),
+}
+
+pub fn parse(text: &str) -> Result<Config, ConfigError> {
+    let mut config = Config::default();
+    let mut section = None;
+
+    for (index, line) in text.lines().enumerate() {
+        let error = |kind| ConfigError {
+            line: index + 1,
+            kind,
+        };
+
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some(header) = line.strip_prefix('[') {
+            let name = header
+                .strip_suffix(']')
+                .ok_or(error(ConfigErrorKind::Syntax("missing `]`")))?
+                .trim();
+            let known = SECTIONS.iter().find(|(known, _)| *known == name);
+            section = Some(
+                known
+                    .ok_or_else(|| error(ConfigErrorKind::UnknownSection(name.to_owned())))?
+                    .0,
+            );
+            continue;
+        }
+
+        let (key, value) = line
+            .split_once('=')
+            .ok_or(error(ConfigErrorKind::Syntax("expected `key = value`")))?;
+        let key = key.trim();
+        if key.is_empty() {
+            return Err(error(ConfigErrorKind::Syntax("missing key before `=`")));
+        }
+        let value = parse_value(value.trim()).map_err(error)?;
+        let section = section.ok_or_else(|| error(ConfigErrorKind::NoSection(key.to_owned())))?;
+
+        config.set(section, key, &value).map_err(error)?;
+    }
+
+    Ok(config)
+}
+
+impl Config {
+    fn set(
+        &mut self,
+        section: &'static str,
+        key: &str,
+        value: &Value,
+    ) -> Result<(), ConfigErrorKind> {
+        let invalid = |expected: &str| ConfigErrorKind::InvalidValue {
+            key: key.to_owned(),
+            expected: expected.to_owned(),
+        };
+
+        match (section, key) {
+            ("playback", "file") => {
+                let file = string(value).ok_or_else(|| invalid("a path"))?;
+                self.file = (!file.is_empty()).then(|| file.to_owned());
+            }
+            ("playback", "dir") => {
+                self.dir = string(value).ok_or_else(|| invalid("a path"))?.to_owned();
+            }
+            ("playback", "loop") => {
+                self.loop_mode = match value {
+                    Value::Bool(false) => LoopMode::Off,
+                    Value::Bool(true) => LoopMode::All,
+                    value => choice(
+                        key,
+                        value,
+                        &[
+                            ("off", LoopMode::Off),
+                            ("file", LoopMode::File),
+                            ("all", LoopMode::All),
+                        ],
+                    )?,
+                }
+            }
+            ("playback", "segment") => {
+                let expected = "`start-end`, like \"1:05-1:30\"";
+                let (start, end) = string(value)
+                    .and_then(|value| value.split_once('-'))
+                    .and_then(|(start, end)| Some((parse_time(start)?, parse_time(end)?)))
+                    .filter(|(start, end)| start < end)
+                    .ok_or_else(|| invalid(expected))?;
+                self.segment = Some((start, end));
+            }
+            ("playback", "shuffle") => {
+                self.shuffle = boolean(value).ok_or_else(|| invalid("true or false"))?
+            }
+            ("playback", "keyframe_index") => {
+                self.keyframe_index = boolean(value).ok_or_else(|| invalid("true or false"))?
+            }
+            ("video", "fit") => {
+                self.fit_mode = choice(
+                    key,
+                    value,
+                    &[
+                        ("fit", FitMode::Fit),
+                        ("fill", FitMode::Fill),
+                        ("stretch", FitMode::Stretch),
+                        ("original", FitMode::Original),
+                    ],
+                )?
+            }
+            ("video", "scaler") => {
+                self.scale_filter = choice(
+                    key,
+                    value,
+                    &[
+                        ("nearest", ScaleFilter::Nearest),
+                        ("bilinear", ScaleFilter::Bilinear),
+                        ("area", ScaleFilter::Area),
+                    ],
+                )?
+            }
+            ("video", "matrix") => {
+                self.color_matrix = choice(
+                    key,
+                    value,
+                    &[
+                        ("auto", None),
+                        ("bt601", Some(Matrix::Bt601)),
+                        ("bt709", Some(Matrix::Bt709)),
+                        ("bt2020", Some(Matrix::Bt2020)),
+                    ],
+                )?
+            }
+            ("video", "range") => {
+                self.color_range = choice(
+                    key,
+                    value,
+                    &[
+                        ("auto", None),
+                        ("limited", Some(Range::Limited)),
+                        ("full", Some(Range::Full)),
+                    ],
+                )?
+            }
+            ("video", "track") => {
+                self.track = match value {
+                    Value::String(auto) if auto.eq_ignore_ascii_case("auto") => Track::Best,
+                    Value::String(title) => Track::Title(title.clone()),
+                    Value::Integer(index) => Track::Index(
+                        usize::try_from(*index).map_err(|_| invalid("a track number from 0"))?,
+                    ),
+                    Value::Bool(_) => return Err(invalid("auto, a track number or a title")),
+                }
+            }
+            ("log", "level") => {
+                self.log_level = choice(
+                    key,
+                    value,
+                    &[
+                        ("quiet", LogLevel::Quiet),
+                        ("error", LogLevel::Error),
+                        ("warning", LogLevel::Warning),
+                        ("info", LogLevel::Info),
+                        ("verbose", LogLevel::Verbose),
+                        ("debug", LogLevel::Debug),
+                    ],
+                )?
+            }
+            ("log", "format_debug") => {
+                self.format_debug = boolean(value).ok_or_else(|| invalid("true or false"))?
+            }
+            ("log", "alloc_report") => {
+                self.alloc_report = boolean(value).ok_or_else(|| invalid("true or false"))?
+            }
+            ("log", "alloc_benchmark") => {
+                self.alloc_benchmark = boolean(value).ok_or_else(|| invalid("true or false"))?
+            }
+            ("io", "buffer_size") => {
+                // AVIO takes the size as a c_int
+                self.buffer_size = integer(value)
+                    .filter(|size| (1..=i32::MAX as i64).contains(size))
+                    .ok_or_else(|| invalid("a positive number of bytes"))?
+                    as usize
+            }
+            ("io", "probe_size") => {
+                self.probe_size = Some(
+                    integer(value)
+                        .filter(|&size| size >= 32)
+                        .ok_or_else(|| invalid("at least 32 bytes"))?,
+                )
+            }
+            ("io", "queue_depth") => {
+                self.queue_depth = integer(value)
+                    .and_then(|depth| usize::try_from(depth).ok())
+                    .filter(|&depth| depth >= 2)
+                    .ok_or_else(|| invalid("at least 2 frames"))?
+            }
+            ("io", "queue_memory") => {
+                self.queue_memory = integer(value)
+                    .and_then(|size| usize::try_from(size).ok())
+                    .filter(|&size| size > 0)
+                    .ok_or_else(|| invalid("a positive number of bytes"))?
+            }
+            ("io", "memory_budget") => {
+                self.memory_budget = Some(
+                    integer(value)
+                        .and_then(|size| usize::try_from(size).ok())
+                        .filter(|&size| size > 0)
+                        .ok_or_else(|| invalid("a positive number of bytes"))?,
+                )
+            }
+            _ => {
+                return Err(ConfigErrorKind::UnknownKey {
+                    section,
+                    key: key.to_owned(),
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn string(value: &Value) -> Option<&str> {
+    match value {
+        Value::String(string) => Some(string),
+        _ => None,
+    }
+}
+
+fn integer(value: &Value) -> Option<i64> {
+    match value {
+        Value::Integer(integer) => Some(*integer),
+        _ => None,
+    }
+}
+
+fn boolean(value: &Value) -> Option<bool> {
+    match value {
+        Value::Bool(bool) => Some(*bool),
+        _ => None,
+    }
+}
+
+/// One of a fixed set of (case-insensitive) names
+fn choice<T: Copy>(key: &str, value: &Value, options: &[(&str, T)]) -> Result<T, ConfigErrorKind> {
+    string(value)
+        .and_then(|value| {
+            options
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(value))
+        })
+        .map(|&(_, option)| option)
+        .ok_or_else(|| {
+            let names: Vec<&str> = options.iter().map(|(name, _)| *name).collect();
+            ConfigErrorKind::InvalidValue {
+                key: key.to_owned(),
+                expected: format!("one of {}", names.join(", ")),
+            }
+        })
+}
+
+/// `[[h:]m:]s[.fraction]`
+fn parse_time(text: &str) -> Option<Duration> {
+    let text = text.trim();
+    let (minutes, seconds) = text.rsplit_once(':').unwrap_or(("", text));
+    let (hours, minutes) = minutes.rsplit_once(':').unwrap_or(("", minutes));
+
+    let field = |text: &str| -> Option<u64> {
+        if text.is_empty() {
+            Some(0)
+        } else {
+            text.parse().ok()
+        }
+    };
+    let (whole, fraction) = seconds.split_once('.').unwrap_or((seconds, ""));
+    if whole.is_empty() || !fraction.bytes().all(|digit| digit.is_ascii_digit()) {
+        return None;
+    }
+    // Only down to the millisecond
+    let millis = fraction
+        .bytes()
+        .chain(core::iter::repeat(b'0'))
+        .take(3)
+        .fold(0, |millis, digit| millis * 10 + (digit - b'0') as u64);
+
+    let seconds = field(hours)? * 3600 + field(minutes)? * 60 + field(whole)?;
+    Some(Duration::from_secs(seconds) + Duration::from_millis(millis))
+}
+
+fn parse_value(text: &str) -> Result<Value, ConfigErrorKind> {
+    if let Some(quoted) = text.strip_prefix('"') {
+        let mut string = String::new();
+        let mut chars = quoted.chars();
+        loop {
+            match chars.next() {
+                Some('"') => break,
+                Some('\\') => match chars.next() {
+                    Some('"') => string.push('"'),
+                    Some('\\') => string.push('\\'),
+                    Some('n') => string.push('\n'),
+                    Some('t') => string.push('\t'),
+                    _ => return Err(ConfigErrorKind::Syntax("unknown escape in string")),
+                },
+                Some(char) => string.push(char),
+                None => return Err(ConfigErrorKind::Syntax("unterminated string")),
+            }
+        }
+        if !chars.as_str().trim().is_empty() {
+            return Err(ConfigErrorKind::Syntax("unexpected text after string"));
+        }
+        return Ok(Value::String(string));
+    }
+
+    match text {
+        "" => Err(ConfigErrorKind::Syntax("missing value after `=`")),
+        "true" => Ok(Value::Bool(true)),
+        "false" => Ok(Value::Bool(false)),
+        _ if text.starts_with(|char: char| char.is_ascii_digit() || char == '-' || char == '+') => {
+            text.replace('_', "")
+                .parse()
+                .map(Value::Integer)
+                .map_err(|_| ConfigErrorKind::Syntax("invalid number"))
+        }
+        // INI-style bare word
+        _ => Ok(Value::String(text.to_string())),
+    }
+}
+
+/// `line` up to the first `#` or `;` that isn't inside a string
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (index, char) in line.char_indices() {
+        match char {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '#' | ';' if !in_string => return &line[..index],
+            _ => {}
+        }
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(text: &str) -> ConfigError {
+        parse(text).unwrap_err()
+    }
+
+    fn invalid(line: usize, key: &str) -> impl Fn(&ConfigError) -> bool {
+        move |error| {
+            error.line == line
+                && matches!(&error.kind, ConfigErrorKind::InvalidValue { key: bad, .. } if bad == key)
+        }
+    }
+
+    #[test]
+    fn every_key() {
+        let config = parse(
+            r#"
+            [playback]
+            file = "videos/a.webm"
+            dir = "videos"
+            loop = "file"
+            segment = "1:05-1:30"
+            shuffle = true
+            keyframe_index = true
+
+            [video]
+            fit = "fill"
+            scaler = "area"
+            matrix = "bt2020"
+            range = "full"
+            track = 2
+
+            [log]
+            level = "debug"
+            format_debug = true
+            alloc_report = true
+            alloc_benchmark = true
+
+            [io]
+            buffer_size = 131_072
+            probe_size = 1000
+            queue_depth = 3
+            queue_memory = 1048576
+            memory_budget = 33554432
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.file.as_deref(), Some("videos/a.webm"));
+        assert_eq!(config.dir, "videos");
+        assert_eq!(config.loop_mode, LoopMode::File);
+        assert_eq!(
+            config.segment,
+            Some((Duration::from_secs(65), Duration::from_secs(90)))
+        );
+        assert!(config.shuffle);
+        assert!(config.keyframe_index);
+        assert_eq!(config.fit_mode, FitMode::Fill);
+        assert_eq!(config.scale_filter, ScaleFilter::Area);
+        assert_eq!(config.color_matrix, Some(Matrix::Bt2020));
+        assert_eq!(config.color_range, Some(Range::Full));
+        assert_eq!(config.track, Track::Index(2));
+        assert_eq!(config.log_level, LogLevel::Debug);
+        assert!(config.format_debug);
+        assert!(config.alloc_report);
+        assert!(config.alloc_benchmark);
+        assert_eq!(config.buffer_size, 131_072);
+        assert_eq!(config.probe_size, Some(1000));
+        assert_eq!(config.queue_depth, 3);
+        assert_eq!(config.queue_memory, 1048576);
+        assert_eq!(config.memory_budget, Some(33554432));
+    }
+
+    #[test]
+    fn defaults() {
+        let config = parse("# nothing but a comment\n\n[video]\n").unwrap();
+        let default = Config::default();
+        assert_eq!(config.file, None);
+        assert_eq!(config.loop_mode, default.loop_mode);
+        assert_eq!(config.buffer_size, 64 * 1024);
+        assert_eq!(config.queue_depth, 4);
+        assert_eq!(config.memory_budget, None);
+    }
+
+    #[test]
+    fn value_forms() {
+        // Bare words, any case; `loop` also takes booleans
+        let config =
+            parse("[playback]\nloop = ALL\n[video]\nfit = Original\nmatrix = auto").unwrap();
+        assert_eq!(config.loop_mode, LoopMode::All);
+        assert_eq!(config.fit_mode, FitMode::Original);
+        assert_eq!(config.color_matrix, None);
+        assert_eq!(
+            parse("[playback]\nloop = false").unwrap().loop_mode,
+            LoopMode::Off
+        );
+        assert_eq!(
+            parse("[playback]\nloop = true").unwrap().loop_mode,
+            LoopMode::All
+        );
+
+        assert_eq!(parse("[video]\ntrack = auto").unwrap().track, Track::Best);
+        assert_eq!(
+            parse("[video]\ntrack = \"Director's Cut\"").unwrap().track,
+            Track::Title("Director's Cut".into())
+        );
+        // An empty file means no file
+        assert_eq!(parse("[playback]\nfile = \"\"").unwrap().file, None);
+    }
+
+    #[test]
+    fn comments() {
+        let config = parse(
+            "; whole line\n[playback] # after a header\nfile = \"a #1; b.webm\" # not part of it\ndir = videos ; nor this",
+        )
+        .unwrap();
+        assert_eq!(config.file.as_deref(), Some("a #1; b.webm"));
+        assert_eq!(config.dir, "videos");
+
+        // An escaped quote doesn't end the string, so the `#` after it is still inside
+        let config = parse(
+            r#"[playback]
+file = "say \"hi #2\".webm" # comment"#,
+        )
+        .unwrap();
+        assert_eq!(config.file.as_deref(), Some("say \"hi #2\".webm"));
+    }
+
+    #[test]
+    fn escapes() {
+        let config = parse(
+            r#"[playback]
+file = "a\\b\tc\nd""#,
+        )
+        .unwrap();
+        assert_eq!(config.file.as_deref(), Some("a\\b\tc\nd"));
+
+        assert_eq!(
+            error("[playback]\nfile = \"a\\qb\""),
+            ConfigError {
+                line: 2,
+                kind: ConfigErrorKind::Syntax("unknown escape in string")
+            }
+        );
+        assert_eq!(
+            error("[playback]\nfile = \"abc").kind,
+            ConfigErrorKind::Syntax("unterminated string")
+        );
+        assert_eq!(
+            error("[playback]\nfile = \"a\" b").kind,
+            ConfigErrorKind::Syntax("unexpected text after string")
+        );
+    }
+
+    #[test]
+    fn structure_errors() {
+        assert_eq!(
+            error("\n\n[vid]"),
+            ConfigError {
+                line: 3,
+                kind: ConfigErrorKind::UnknownSection("vid".into())
+            }
+        );
+        assert_eq!(
+            error("[video]\n\nfitt = fit"),
+            ConfigError {
+                line: 3,
+                kind: ConfigErrorKind::UnknownKey {
+                    section: "video",
+                    key: "fitt".into()
+                }
+            }
+        );
+        // Keys are only known in their own section
+        assert_eq!(
+            error("[io]\nfit = fit").kind,
+            ConfigErrorKind::UnknownKey {
+                section: "io",
+                key: "fit".into()
+            }
+        );
+        assert_eq!(
+            error("fit = fit"),
+            ConfigError {
+                line: 1,
+                kind: ConfigErrorKind::NoSection("fit".into())
+            }
+        );
+        assert_eq!(
+            error("[playback").kind,
+            ConfigErrorKind::Syntax("missing `]`")
+        );
+        assert_eq!(
+            error("[playback]\nfile").kind,
+            ConfigErrorKind::Syntax("expected `key = value`")
+        );
+        assert_eq!(
+            error("[playback]\n= 3").kind,
+            ConfigErrorKind::Syntax("missing key before `=`")
+        );
+        assert_eq!(
+            error("[playback]\nloop =").kind,
+            ConfigErrorKind::Syntax("missing value after `=`")
+        );
+        assert_eq!(
+            error("[io]\nbuffer_size = 12k").kind,
+            ConfigErrorKind::Syntax("invalid number")
+        );
+
+        let message = error("[video]\nfitt = fit").to_string();
+        assert_eq!(
+            message,
+            "line 2: unknown key `fitt` in [video], expected one of fit, scaler, matrix, range, track"
+        );
+    }
+
+    #[test]
+    fn bad_values() {
+        let cases = [
+            "[playback]\nfile = 3",
+            "[playback]\nshuffle = yes",
+            "[playback]\nloop = sometimes",
+            "[video]\nfit = \"squash\"",
+            "[video]\nscaler = 2",
+            "[video]\nmatrix = bt2100",
+            "[video]\nrange = tv",
+            "[video]\ntrack = -1",
+            "[video]\ntrack = true",
+            "[log]\nlevel = loud",
+            "[io]\nbuffer_size = 0",
+            "[io]\nbuffer_size = 4294967296",
+            "[io]\nbuffer_size = \"64k\"",
+            "[io]\nprobe_size = 31",
+            "[io]\nqueue_depth = 1",
+            "[io]\nqueue_memory = 0",
+            "[io]\nmemory_budget = -5",
+        ];
+        for text in cases {
+            let key = text.split_once('\n').unwrap().1.split_once(' ').unwrap().0;
+            assert!(invalid(2, key)(&error(text)), "{text:?}: {:?}", error(text));
+        }
+    }
+
+    #[test]
+    fn segments() {
+        let segment = |text: &str| {
+            parse(&format!("[playback]\nsegment = \"{text}\""))
+                .map(|config| config.segment.unwrap())
+        };
+        assert_eq!(
+            segment("5-1:00:00"),
+            Ok((Duration::from_secs(5), Duration::from_secs(3600)))
+        );
+        assert_eq!(
+            segment("0.5-2.1234"),
+            Ok((Duration::from_millis(500), Duration::from_millis(2123)))
+        );
+        assert_eq!(
+            segment(" 1:05 - 1:30.25 "),
+            Ok((Duration::from_secs(65), Duration::from_millis(90250)))
+        );
+
+        // Has to end after it starts
+        for bad in ["30-5", "5-5", "a-5", "5", "-5", "1:-5", ".5-2", "1.x-2"] {
+            assert!(
+                segment(bad).as_ref().is_err_and(invalid(2, "segment")),
+                "{bad:?}"
+            );
+        }
+        assert!(invalid(2, "segment")(&error("[playback]\nsegment = 5")));
+    }
+
+    #[test]
+    fn times() {
+        assert_eq!(parse_time("0"), Some(Duration::ZERO));
+        assert_eq!(parse_time("90"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_time("1:30"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_time("1:02:03"), Some(Duration::from_secs(3723)));
+        assert_eq!(parse_time("1.5"), Some(Duration::from_millis(1500)));
+        assert_eq!(parse_time("0:00.0019"), Some(Duration::from_millis(1)));
+        assert_eq!(parse_time(":30"), Some(Duration::from_secs(30)));
+        for bad in ["", ".5", "1:", "a", "1:2:3:4", "1.-5", "-1"] {
+            assert_eq!(parse_time(bad), None, "{bad:?}");
+        }
+    }
+}
@@ -0,0 +1,288 @@
+//! Controller input -> playback commands
+//!
+//! Works on plain snapshots of which buttons are held, so it can be driven by a fake controller.
+//! Commands fire when a button goes down; seeking also repeats while the button is held. While
+//! paused, the short seeks step a single frame instead.
+
+use core::{ops::BitOr, time::Duration};
+
+/// Set of controller buttons
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Buttons(u16);
+
+impl Buttons {
+    pub const NONE: Self = Self(0);
+    pub const A: Self = Self(1 << 0);
+    pub const B: Self = Self(1 << 1);
+    pub const X: Self = Self(1 << 2);
+    pub const Y: Self = Self(1 << 3);
+    pub const UP: Self = Self(1 << 4);
+    pub const DOWN: Self = Self(1 << 5);
+    pub const LEFT: Self = Self(1 << 6);
+    pub const RIGHT: Self = Self(1 << 7);
+    pub const L1: Self = Self(1 << 8);
+    pub const L2: Self = Self(1 << 9);
+    pub const R1: Self = Self(1 << 10);
+    pub const R2: Self = Self(1 << 11);
+
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// `self` with `buttons` added if `pressed`
+    pub const fn with(self, buttons: Self, pressed: bool) -> Self {
+        if pressed {
+            Self(self.0 | buttons.0)
+        } else {
+            self
+        }
+    }
+}
+
+impl BitOr for Buttons {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    TogglePause,
+    SeekForward(Duration),
+    SeekBackward(Duration),
+    /// Absolute position, e.g. from the scrub bar
+    SeekTo(Duration),
+    NextFile,
+    PreviousFile,
+    /// Back to the menu
+    Stop,
+    Faster,
+    Slower,
+    NormalSpeed,
+    /// Set loop point A, then B, then clear the A-B repeat
+    MarkLoop,
+    /// One frame on/back, while paused
+    StepForward,
+    StepBackward,
+}
+
+struct Binding {
+    buttons: Buttons,
+    command: Command,
+    repeats: bool,
+}
+
+const fn bind(buttons: Buttons, command: Command, repeats: bool) -> Binding {
+    Binding {
+        buttons,
+        command,
+        repeats,
+    }
+}
+
+const SHORT_SEEK: Duration = Duration::from_secs(5);
+const LONG_SEEK: Duration = Duration::from_secs(30);
+
+const BINDINGS: [Binding; 12] = [
+    bind(Buttons::A, Command::TogglePause, false),
+    bind(Buttons::B, Command::Stop, false),
+    bind(Buttons::LEFT, Command::SeekBackward(SHORT_SEEK), true),
+    bind(Buttons::RIGHT, Command::SeekForward(SHORT_SEEK), true),
+    bind(Buttons::L2, Command::SeekBackward(LONG_SEEK), true),
+    bind(Buttons::R2, Command::SeekForward(LONG_SEEK), true),
+    bind(Buttons::L1, Command::PreviousFile, false),
+    bind(Buttons::R1, Command::NextFile, false),
+    bind(Buttons::UP, Command::Faster, false),
+    bind(Buttons::DOWN, Command::Slower, false),
+    bind(Buttons::X, Command::NormalSpeed, false),
+    bind(Buttons::Y, Command::MarkLoop, false),
+];
+
+/// Playback speeds the speed buttons step through, in percent
+pub const SPEEDS: [u32; 9] = [25, 50, 75, 100, 125, 150, 200, 300, 400];
+
+/// Next speed up/down from `current`, staying put at either end
+pub fn step_speed(current: u32, faster: bool) -> u32 {
+    let next = if faster {
+        SPEEDS.iter().find(|&&speed| speed > current)
+    } else {
+        SPEEDS.iter().rev().find(|&&speed| speed < current)
+    };
+    next.copied().unwrap_or(current)
+}
+
+/// Hold a seek button this long before it starts repeating
+const REPEAT_DELAY: Duration = Duration::from_millis(400);
+const REPEAT_INTERVAL: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Default)]
+pub struct Controls {
+    held: Buttons,
+    /// When each repeating binding fires next, while it's held
+    repeat_at: [Option<Duration>; BINDINGS.len()],
+}
+
+impl Controls {
+    /// Feed the buttons held at time `now`, get back whatever commands that triggers
+    pub fn update(
+        &mut self,
+        buttons: Buttons,
+        now: Duration,
+        paused: bool,
+    ) -> impl Iterator<Item = Command> + use<> {
+        let mut fired = 0u32;
+        for (index, binding) in BINDINGS.iter().enumerate() {
+            let held = buttons.contains(binding.buttons);
+            let was_held = self.held.contains(binding.buttons);
+
+            match (held, was_held) {
+                (true, false) => {
+                    fired |= 1 << index;
+                    self.repeat_at[index] = binding.repeats.then(|| now + REPEAT_DELAY);
+                }
+                (true, true) => {
+                    if let Some(at) = self.repeat_at[index]
+                        && now >= at
+                    {
+                        fired |= 1 << index;
+                        self.repeat_at[index] = Some(at + REPEAT_INTERVAL);
+                    }
+                }
+                (false, _) => self.repeat_at[index] = None,
+            }
+        }
+        self.held = buttons;
+
+        BINDINGS
+            .iter()
+            .enumerate()
+            .filter(move |(index, _)| fired & 1 << index != 0)
+            .map(move |(_, binding)| match binding.command {
+                Command::SeekForward(SHORT_SEEK) if paused => Command::StepForward,
+                Command::SeekBackward(SHORT_SEEK) if paused => Command::StepBackward,
+                command => command,
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    /// Feed `(time, buttons)` snapshots in order & collect what each one fired
+    fn run(paused: bool, snapshots: &[(u64, Buttons)]) -> Vec<Vec<Command>> {
+        let mut controls = Controls::default();
+        snapshots
+            .iter()
+            .map(|&(time, buttons)| controls.update(buttons, ms(time), paused).collect())
+            .collect()
+    }
+
+    #[test]
+    fn fires_on_press_only() {
+        let fired = run(
+            false,
+            &[
+                (0, Buttons::A),
+                (10, Buttons::A),
+                (1000, Buttons::A),
+                (1010, Buttons::NONE),
+                (1020, Buttons::A | Buttons::R1),
+                (1030, Buttons::R1),
+            ],
+        );
+        assert_eq!(
+            fired,
+            [
+                &[Command::TogglePause][..],
+                &[],
+                &[],
+                &[],
+                &[Command::TogglePause, Command::NextFile],
+                &[],
+            ]
+        );
+    }
+
+    #[test]
+    fn seeks_repeat_while_held() {
+        let seek = Command::SeekForward(SHORT_SEEK);
+        let fired = run(
+            false,
+            &[
+                (0, Buttons::RIGHT),
+                (399, Buttons::RIGHT),
+                (400, Buttons::RIGHT),
+                (599, Buttons::RIGHT),
+                (600, Buttons::RIGHT),
+                (800, Buttons::RIGHT),
+                (810, Buttons::NONE),
+                // Let go & press again: the delay starts over
+                (820, Buttons::RIGHT),
+                (1100, Buttons::RIGHT),
+                (1220, Buttons::RIGHT),
+            ],
+        );
+        let counts: Vec<usize> = fired.iter().map(Vec::len).collect();
+        assert_eq!(counts, [1, 0, 1, 0, 1, 1, 0, 1, 0, 1]);
+        assert!(fired.iter().flatten().all(|&command| command == seek));
+
+        // Non-seek buttons never repeat
+        let fired = run(
+            false,
+            &[(0, Buttons::UP), (400, Buttons::UP), (5000, Buttons::UP)],
+        );
+        assert_eq!(fired, [&[Command::Faster][..], &[], &[]]);
+    }
+
+    #[test]
+    fn short_seeks_step_while_paused() {
+        let fired = run(
+            true,
+            &[
+                (0, Buttons::RIGHT | Buttons::LEFT),
+                (400, Buttons::RIGHT),
+                (410, Buttons::R2),
+            ],
+        );
+        assert_eq!(
+            fired,
+            [
+                &[Command::StepBackward, Command::StepForward][..],
+                &[Command::StepForward],
+                // Long seeks still seek
+                &[Command::SeekForward(LONG_SEEK)],
+            ]
+        );
+    }
+
+    #[test]
+    fn speed_steps() {
+        assert_eq!(step_speed(100, true), 125);
+        assert_eq!(step_speed(100, false), 75);
+        assert_eq!(step_speed(SPEEDS[0], false), SPEEDS[0]);
+        assert_eq!(step_speed(SPEEDS[8], true), SPEEDS[8]);
+        assert_eq!(step_speed(SPEEDS[0], true), SPEEDS[1]);
+        assert_eq!(step_speed(SPEEDS[8], false), SPEEDS[7]);
+        // In between two steps goes to the nearest one that way
+        assert_eq!(step_speed(110, true), 125);
+        assert_eq!(step_speed(110, false), 100);
+    }
+
+    #[test]
+    fn buttons() {
+        let held = Buttons::NONE.with(Buttons::A, true).with(Buttons::B, false);
+        assert!(held.contains(Buttons::A));
+        assert!(!held.contains(Buttons::B));
+        assert!(!held.contains(Buttons::A | Buttons::B));
+        assert!(held.contains(Buttons::NONE));
+    }
+}
@@ -0,0 +1,108 @@
+use alloc::{borrow::ToOwned, string::String};
+use core::{
+    ffi::{CStr, c_int},
+    fmt,
+};
+
+use crate::{
+    config::{ConfigError, Track},
+    ffmpeg,
+};
+
+pub const AVERROR_EOF: c_int =
+    -(((b'E' as u32) | (b'O' as u32) << 8 | (b'F' as u32) << 16 | (b' ' as u32) << 24) as c_int);
+pub const AVERROR_DECODER_NOT_FOUND: c_int =
+    -((0xf8 | (b'D' as u32) << 8 | (b'E' as u32) << 16 | (b'C' as u32) << 24) as c_int);
+pub const AVERROR_EAGAIN: c_int = -(ffmpeg::EAGAIN as c_int);
+pub const AVERROR_ENOMEM: c_int = -(ffmpeg::ENOMEM as c_int);
+pub const AVERROR_EIO: c_int = -(ffmpeg::EIO as c_int);
+pub const AVERROR_EINVAL: c_int = -(ffmpeg::EINVAL as c_int);
+
+#[derive(Debug)]
+pub enum PlayerError {
+    /// An ffmpeg call failed with `AVERROR` `code`
+    Ffmpeg { code: c_int, message: String },
+    /// Reading the video off the SD card failed
+    Filesystem(vexide::io::Error),
+    /// Decoder handed us frames we don't know how to draw
+    UnsupportedPixelFormat(ffmpeg::AVPixelFormat),
+    /// Out of memory (or over ffmpeg's budget), either on our side or ffmpeg's
+    Alloc,
+    /// No playable videos in the chosen directory/playlist
+    NothingToPlay,
+    /// `player.toml` didn't parse
+    Config(ConfigError),
+    /// The file has no video track matching the configured one
+    NoSuchTrack(Track),
+}
+
+impl PlayerError {
+    /// Turn an `AVERROR` into an error, decoding its message with `av_strerror`
+    pub fn from_av(code: c_int) -> Self {
+        if code == AVERROR_ENOMEM {
+            // Don't try allocating a message when we're already out of memory
+            return Self::Alloc;
+        }
+
+        let mut str = [0u8; 256];
+        unsafe {
+            ffmpeg::av_strerror(code, str.as_mut_ptr().cast(), str.len());
+        }
+
+        let message = CStr::from_bytes_until_nul(&str)
+            .ok()
+            .and_then(|message| message.to_str().ok())
+            .unwrap_or("Unknown error")
+            .to_owned();
+        Self::Ffmpeg { code, message }
+    }
+}
+
+/// Pass through non-negative ffmpeg results, turn negative ones into errors
+pub fn av_check(result: c_int) -> Result<c_int, PlayerError> {
+    if result < 0 {
+        Err(PlayerError::from_av(result))
+    } else {
+        Ok(result)
+    }
+}
+
+impl From<vexide::io::Error> for PlayerError {
+    fn from(err: vexide::io::Error) -> Self {
+        Self::Filesystem(err)
+    }
+}
+
+impl From<ConfigError> for PlayerError {
+    fn from(err: ConfigError) -> Self {
+        Self::Config(err)
+    }
+}
+
+impl fmt::Display for PlayerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ffmpeg { code, message } => write!(f, "ffmpeg error {code}: {message}"),
+            Self::Filesystem(err) => write!(f, "Filesystem error: {err}"),
+            Self::UnsupportedPixelFormat(format) => {
+                let name = unsafe { ffmpeg::av_get_pix_fmt_name(*format) };
+                if name.is_null() {
+                    write!(f, "Unsupported pixel format: {format}")
+                } else {
+                    let name = unsafe { CStr::from_ptr(name) };
+                    write!(f, "Unsupported pixel format: {}", name.to_string_lossy())
+                }
+            }
+            Self::Alloc => write!(f, "Video too large for memory"),
+            Self::NothingToPlay => write!(f, "No videos to play"),
+            Self::Config(err) => write!(f, "player.toml {err}"),
+            Self::NoSuchTrack(Track::Best) => write!(f, "No video track"),
+            Self::NoSuchTrack(Track::Index(index)) => write!(f, "No video track number {index}"),
+            Self::NoSuchTrack(Track::Title(title)) => {
+                write!(f, "No video track titled \"{title}\"")
+            }
+        }
+    }
+}
+
+impl core::error::Error for PlayerError {}
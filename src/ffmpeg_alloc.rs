@@ -0,0 +1,1341 @@
+//! `malloc` & friends for ffmpeg, on top of the Rust global allocator
+//!
+//! Allocations are capped at a budget (see [`set_budget`]); past it, or once the heap runs out,
+//! they return null like C's would, and ffmpeg gives up with `AVERROR(ENOMEM)` instead of us
+//! panicking.
+//!
+//! Big blocks (frame planes, mostly) get pooled by size when freed instead of going back to the
+//! heap, since a decoder frees & reallocates the same few sizes over and over, and over a long
+//! video that chops the heap up until nothing big fits anymore.
+//!
+//! Each block carries a small [`Header`] in front of it with the size & alignment `free` needs to
+//! hand it back, so nothing gets looked up (or allocated) on the way in or out. Headers also link
+//! every live block into a list, for the report of whatever's still allocated. Nothing in here
+//! depends on vexide, so the `vexide_*` functions get tested on the host like any other Rust code.
+//!
+//! With the `heap-guard` feature, blocks also get canaries on either side, checked on `free` &
+//! `realloc`, and freed memory gets poisoned, to catch native code writing where it shouldn't.
+//! Freed blocks are also held back from the heap for a while, so that freeing one again is caught
+//! instead of reading a header the heap has already reused.
+//!
+//! ```text
+//!  base                                  ptr
+//!  | padding | Header | canary (GUARD)   | size bytes ... | canary (GUARD)
+//!  '-------- header_space(align) --------'
+//! ```
+
+use core::{
+    alloc::Layout,
+    cell::UnsafeCell,
+    ffi::{c_int, c_size_t, c_void},
+    fmt,
+    ptr::null_mut,
+    time::Duration,
+};
+
+/// Power-of-two size classes in the histogram; the last one takes everything bigger
+pub const SIZE_CLASSES: usize = 24;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stats {
+    /// Bytes currently allocated
+    pub current: usize,
+    pub peak: usize,
+    /// Blocks currently allocated
+    pub live: usize,
+    /// Blocks ever allocated, reallocs included
+    pub allocations: u64,
+    pub frees: u64,
+    /// Biggest single block ever asked for
+    pub largest: usize,
+    /// Allocations turned down, for going over budget or the heap running out
+    pub failed: u64,
+    /// Bytes of freed blocks kept around for reuse
+    pub pooled: usize,
+    /// Allocations big enough to pool that a pooled block was reused for
+    pub pool_hits: u64,
+    /// Allocations big enough to pool that had to go to the heap
+    pub pool_misses: u64,
+    /// Allocations by size, class `n` holding sizes up to `1 << n`
+    pub size_classes: [u64; SIZE_CLASSES],
+    /// Frees of blocks that were already freed, caught while the block is still pooled (or with
+    /// `heap-guard`, quarantined). Once it's back with the heap, freeing it again is as undefined
+    /// as in C
+    pub double_frees: u64,
+    /// The most recent of those
+    pub last_double_free: Option<usize>,
+    /// Blocks found with a canary overwritten; always 0 without the `heap-guard` feature
+    pub corrupted: u64,
+    /// The most recent of those
+    pub last_corruption: Option<Corruption>,
+}
+
+/// A block whose canaries didn't survive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Corruption {
+    pub ptr: usize,
+    pub size: usize,
+    /// Something wrote in front of the block
+    pub underrun: bool,
+    /// Something wrote past the end of it
+    pub overrun: bool,
+}
+
+impl fmt::Display for Corruption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let damage = match (self.underrun, self.overrun) {
+            (true, true) => "written before & after",
+            (true, false) => "written before",
+            _ => "written past",
+        };
+        write!(f, "{:#x}, {} bytes: {damage}", self.ptr, self.size)
+    }
+}
+
+impl Stats {
+    const fn new() -> Self {
+        Self {
+            current: 0,
+            peak: 0,
+            live: 0,
+            allocations: 0,
+            frees: 0,
+            largest: 0,
+            failed: 0,
+            pooled: 0,
+            pool_hits: 0,
+            pool_misses: 0,
+            size_classes: [0; SIZE_CLASSES],
+            double_frees: 0,
+            last_double_free: None,
+            corrupted: 0,
+            last_corruption: None,
+        }
+    }
+
+    fn allocated(&mut self, size: usize) {
+        self.current += size;
+        self.peak = self.peak.max(self.current);
+        self.live += 1;
+        self.allocations += 1;
+        self.largest = self.largest.max(size);
+        self.size_classes[size_class(size)] += 1;
+    }
+
+    fn freed(&mut self, size: usize) {
+        self.current -= size;
+        self.live -= 1;
+        self.frees += 1;
+    }
+
+    /// Share of poolable allocations the pool served, in percent
+    pub fn pool_hit_rate(&self) -> u64 {
+        self.pool_hits * 100 / (self.pool_hits + self.pool_misses).max(1)
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} bytes in {} blocks (peak {}), {} allocations, {} frees, largest {} bytes, {} failed, {} double frees, {} corrupted",
+            self.current,
+            self.live,
+            self.peak,
+            self.allocations,
+            self.frees,
+            self.largest,
+            self.failed,
+            self.double_frees,
+            self.corrupted
+        )?;
+        write!(
+            f,
+            "; pool {}% hits ({} of {}), {} bytes pooled",
+            self.pool_hit_rate(),
+            self.pool_hits,
+            self.pool_hits + self.pool_misses,
+            self.pooled
+        )
+    }
+}
+
+/// Histogram bucket for a block of `size` bytes
+pub fn size_class(size: usize) -> usize {
+    size.checked_next_power_of_two()
+        .map_or(SIZE_CLASSES - 1, |class| class.trailing_zeros() as usize)
+        .min(SIZE_CLASSES - 1)
+}
+
+/// Smallest alignment handed out; what C's `malloc` promises
+const MIN_ALIGN: usize = 8;
+const LIVE: usize = 0x4c49_5645;
+const FREED: usize = 0x4652_4545;
+const POOLED: usize = 0x504f_4f4c;
+
+/// Canary bytes on either side of each block
+#[cfg(feature = "heap-guard")]
+const GUARD: usize = 16;
+#[cfg(not(feature = "heap-guard"))]
+const GUARD: usize = 0;
+const CANARY: u8 = 0xfd;
+/// What freed blocks get filled with
+const POISON: u8 = 0xdd;
+
+/// Blocks at least this big (header included) get pooled when freed
+const POOL_MIN: usize = 16 * 1024;
+/// Most bytes kept pooled at once
+const POOL_BYTES: usize = 4 * 1024 * 1024;
+/// Distinct block sizes pooled at once
+const POOL_CLASSES: usize = 8;
+
+/// Freed blocks held back from the heap at once, so double frees find their header intact
+#[cfg(feature = "heap-guard")]
+const QUARANTINE_BLOCKS: usize = 64;
+#[cfg(not(feature = "heap-guard"))]
+const QUARANTINE_BLOCKS: usize = 0;
+/// Most bytes held back at once; bigger blocks go straight back to the heap
+const QUARANTINE_BYTES: usize = 1024 * 1024;
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct Header {
+    /// As asked for, not counting the header
+    size: usize,
+    /// Of the whole block, so at least [`MIN_ALIGN`]
+    align: usize,
+    /// Neighbours in the list of live blocks; `next` links pooled blocks of a size class instead
+    prev: *mut Header,
+    next: *mut Header,
+    /// [`LIVE`] until freed, pooled or quarantined; anything else means it isn't ours to free
+    magic: usize,
+}
+
+/// Bytes in front of a block aligned to `align`: the header & front canary, padded to keep the
+/// block aligned
+fn header_space(align: usize) -> Option<usize> {
+    (size_of::<Header>() + GUARD).checked_next_multiple_of(align)
+}
+
+/// Layout of the whole allocation for `size` bytes at `align`, header included
+fn block_layout(size: usize, align: usize) -> Option<Layout> {
+    if !align.is_power_of_two() {
+        return None;
+    }
+    let align = align.max(MIN_ALIGN);
+    Layout::from_size_align(
+        header_space(align)?.checked_add(size)?.checked_add(GUARD)?,
+        align,
+    )
+    .ok()
+}
+
+/// # Safety
+/// `ptr` has to have come from [`allocate`]
+unsafe fn header_of(ptr: *mut c_void) -> *mut Header {
+    unsafe { ptr.cast::<u8>().sub(GUARD).cast::<Header>().sub(1) }
+}
+
+/// Inverse of [`header_of`]
+///
+/// # Safety
+/// `header` has to belong to a block that hasn't been handed back to the heap
+unsafe fn ptr_of(header: *mut Header) -> *mut c_void {
+    unsafe { header.add(1).cast::<u8>().add(GUARD).cast() }
+}
+
+/// # Safety
+/// `header` has to be live
+unsafe fn layout_of(header: *const Header) -> Layout {
+    unsafe {
+        let Header { size, align, .. } = *header;
+        Layout::from_size_align_unchecked(
+            header_space(align).unwrap_unchecked() + size + GUARD,
+            align,
+        )
+    }
+}
+
+/// # Safety
+/// `header` has to belong to a block that hasn't been handed back to the heap
+unsafe fn block_base(header: *mut Header) -> *mut u8 {
+    unsafe {
+        let ptr = ptr_of(header).cast::<u8>();
+        ptr.sub(header_space((*header).align).unwrap_unchecked())
+    }
+}
+
+/// # Safety
+/// `header` has to be live
+unsafe fn write_guards(header: *mut Header) {
+    if GUARD == 0 {
+        return;
+    }
+    unsafe {
+        let ptr = ptr_of(header).cast::<u8>();
+        ptr.sub(GUARD).write_bytes(CANARY, GUARD);
+        ptr.add((*header).size).write_bytes(CANARY, GUARD);
+    }
+}
+
+/// `None` if both canaries are intact
+///
+/// # Safety
+/// `header` has to be live
+unsafe fn check_guards(header: *mut Header) -> Option<Corruption> {
+    if GUARD == 0 {
+        return None;
+    }
+    unsafe {
+        let ptr = ptr_of(header).cast::<u8>();
+        let size = (*header).size;
+        let intact = |guard: *const u8| {
+            core::slice::from_raw_parts(guard, GUARD)
+                .iter()
+                .all(|&byte| byte == CANARY)
+        };
+        let underrun = !intact(ptr.sub(GUARD));
+        let overrun = !intact(ptr.add(size));
+
+        (underrun || overrun).then_some(Corruption {
+            ptr: ptr as usize,
+            size,
+            underrun,
+            overrun,
+        })
+    }
+}
+
+/// Freed blocks of one exact layout
+#[derive(Clone, Copy)]
+struct Class {
+    layout: Layout,
+    /// Linked through [`Header::next`]
+    free: *mut Header,
+    count: usize,
+}
+
+struct Pool {
+    classes: [Option<Class>; POOL_CLASSES],
+    /// Total of every pooled block's layout size
+    bytes: usize,
+}
+
+impl Pool {
+    /// A pooled block of exactly `layout`, which stays marked [`POOLED`] until reused
+    fn take(&mut self, layout: Layout) -> Option<*mut Header> {
+        let slot = self
+            .classes
+            .iter_mut()
+            .find(|class| class.is_some_and(|class| class.layout == layout))?;
+        let class = slot.as_mut()?;
+
+        let header = class.free;
+        class.free = unsafe { (*header).next };
+        class.count -= 1;
+        if class.count == 0 {
+            // Make room for whatever size comes next
+            *slot = None;
+        }
+        self.bytes -= layout.size();
+        Some(header)
+    }
+
+    /// Keep `header`'s block for reuse. `false` if the pool's full, in which case it's up to the
+    /// caller to free it
+    ///
+    /// # Safety
+    /// `header` has to be freed already & not in any list
+    unsafe fn put(&mut self, header: *mut Header, layout: Layout) -> bool {
+        if self.bytes + layout.size() > POOL_BYTES {
+            return false;
+        }
+        let slot = match self
+            .classes
+            .iter()
+            .position(|class| class.is_some_and(|class| class.layout == layout))
+            .or_else(|| self.classes.iter().position(Option::is_none))
+        {
+            Some(index) => &mut self.classes[index],
+            None => return false,
+        };
+        let class = slot.get_or_insert(Class {
+            layout,
+            free: null_mut(),
+            count: 0,
+        });
+
+        unsafe {
+            (*header).magic = POOLED;
+            (*header).next = class.free;
+        }
+        class.free = header;
+        class.count += 1;
+        self.bytes += layout.size();
+        true
+    }
+
+    /// Hand every pooled block back to the heap
+    fn drain(&mut self) {
+        for slot in &mut self.classes {
+            let Some(class) = slot.take() else {
+                continue;
+            };
+            let mut header = class.free;
+            while !header.is_null() {
+                unsafe {
+                    let next = (*header).next;
+                    (*header).magic = FREED;
+                    alloc::alloc::dealloc(block_base(header), class.layout);
+                    header = next;
+                }
+            }
+        }
+        self.bytes = 0;
+    }
+}
+
+/// Freed blocks that didn't get pooled, kept [`FREED`] for a while before going back to the heap.
+/// Only ever holds anything with the `heap-guard` feature
+struct Quarantine {
+    /// Ring buffer, oldest at `start`
+    blocks: [*mut Header; QUARANTINE_BLOCKS],
+    start: usize,
+    len: usize,
+    /// Total of every quarantined block's layout size
+    bytes: usize,
+}
+
+impl Quarantine {
+    /// Hold on to `header`'s block, handing the oldest ones back to the heap to make room. `false`
+    /// if it's too big, in which case it's up to the caller to free it
+    ///
+    /// # Safety
+    /// `header` has to be freed already & not in any list
+    unsafe fn put(&mut self, header: *mut Header, layout: Layout) -> bool {
+        if QUARANTINE_BLOCKS == 0 || layout.size() > QUARANTINE_BYTES {
+            return false;
+        }
+        while self.len == QUARANTINE_BLOCKS || self.bytes + layout.size() > QUARANTINE_BYTES {
+            self.release_oldest();
+        }
+
+        unsafe { (*header).magic = FREED };
+        self.blocks[self.slot(self.len)] = header;
+        self.len += 1;
+        self.bytes += layout.size();
+        true
+    }
+
+    fn release_oldest(&mut self) {
+        let header = self.blocks[self.start];
+        self.start = self.slot(1);
+        self.len -= 1;
+        unsafe {
+            // Headers stay intact in quarantine; only the data behind them gets poisoned
+            let layout = layout_of(header);
+            self.bytes -= layout.size();
+            alloc::alloc::dealloc(block_base(header), layout);
+        }
+    }
+
+    /// Index into `blocks` of the `index`th oldest block
+    fn slot(&self, index: usize) -> usize {
+        // Never called without `heap-guard`, where there aren't any slots to wrap around
+        (self.start + index)
+            .checked_rem(QUARANTINE_BLOCKS)
+            .unwrap_or_default()
+    }
+
+    /// Hand every quarantined block back to the heap
+    fn drain(&mut self) {
+        while self.len > 0 {
+            self.release_oldest();
+        }
+    }
+}
+
+struct Tracker {
+    /// Most recently allocated live block
+    head: *mut Header,
+    /// Most [`Stats::current`] may grow to
+    budget: usize,
+    pool: Pool,
+    quarantine: Quarantine,
+    stats: Stats,
+    /// See [`on_corruption`]
+    on_corruption: Option<fn(Corruption)>,
+}
+
+impl Tracker {
+    /// Whether `size` more bytes stay within budget, counting pooled blocks, which get let go of
+    /// first if that's what it takes. Counts a failure if not
+    fn fits(&mut self, size: usize) -> bool {
+        let fits = |tracker: &Self| {
+            tracker
+                .stats
+                .current
+                .saturating_add(tracker.pool.bytes)
+                .saturating_add(size)
+                <= tracker.budget
+        };
+        if !fits(self) && self.pool.bytes > 0 {
+            self.pool.drain();
+        }
+        if !fits(self) {
+            self.stats.failed += 1;
+            return false;
+        }
+        true
+    }
+
+    /// Hand pooled & quarantined blocks back to the heap. `false` if there weren't any
+    fn release(&mut self) -> bool {
+        let held = self.pool.bytes + self.quarantine.bytes > 0;
+        self.pool.drain();
+        self.quarantine.drain();
+        held
+    }
+
+    /// A fresh block from the heap, emptying the pool & quarantine to make room if needed. Counts a
+    /// failure if that still doesn't do it
+    fn heap_alloc(&mut self, layout: Layout) -> *mut u8 {
+        let mut base = unsafe { alloc::alloc::alloc(layout) };
+        if base.is_null() && self.release() {
+            base = unsafe { alloc::alloc::alloc(layout) };
+        }
+        if base.is_null() {
+            self.stats.failed += 1;
+        }
+        base
+    }
+
+    /// # Safety
+    /// `header` has to be valid & not already in the list
+    unsafe fn link(&mut self, header: *mut Header) {
+        unsafe {
+            (*header).prev = null_mut();
+            (*header).next = self.head;
+            if let Some(next) = self.head.as_mut() {
+                next.prev = header;
+            }
+        }
+        self.head = header;
+    }
+
+    /// # Safety
+    /// `header` has to be in the list
+    unsafe fn unlink(&mut self, header: *mut Header) {
+        unsafe {
+            let Header { prev, next, .. } = *header;
+            match prev.as_mut() {
+                Some(prev) => prev.next = next,
+                None => self.head = next,
+            }
+            if let Some(next) = next.as_mut() {
+                next.prev = prev;
+            }
+        }
+    }
+
+    fn double_free(&mut self, ptr: *mut c_void) {
+        self.stats.double_frees += 1;
+        self.stats.last_double_free = Some(ptr as usize);
+    }
+
+    /// Take `header`'s block out of the live list & pool, quarantine or free it
+    ///
+    /// # Safety
+    /// `header` has to be live
+    unsafe fn free(&mut self, header: *mut Header) {
+        unsafe {
+            if cfg!(feature = "heap-guard") {
+                ptr_of(header)
+                    .cast::<u8>()
+                    .write_bytes(POISON, (*header).size);
+            }
+
+            let layout = layout_of(header);
+            self.unlink(header);
+            self.stats.freed((*header).size);
+
+            if layout.size() >= POOL_MIN && self.pool.put(header, layout) {
+                return;
+            }
+            if self.quarantine.put(header, layout) {
+                return;
+            }
+            (*header).magic = FREED;
+            alloc::alloc::dealloc(block_base(header), layout);
+        }
+    }
+
+    /// Count `header`'s block if its canaries got overwritten
+    ///
+    /// # Safety
+    /// `header` has to be live
+    unsafe fn verify(&mut self, header: *mut Header) {
+        if let Some(corruption) = unsafe { check_guards(header) } {
+            self.stats.corrupted += 1;
+            self.stats.last_corruption = Some(corruption);
+            if let Some(on_corruption) = self.on_corruption {
+                on_corruption(corruption);
+            }
+        }
+    }
+}
+
+struct AllocTracker(UnsafeCell<Tracker>);
+static ALLOCATED: AllocTracker = AllocTracker(UnsafeCell::new(Tracker {
+    head: null_mut(),
+    budget: usize::MAX,
+    pool: Pool {
+        classes: [None; POOL_CLASSES],
+        bytes: 0,
+    },
+    quarantine: Quarantine {
+        blocks: [null_mut(); QUARANTINE_BLOCKS],
+        start: 0,
+        len: 0,
+        bytes: 0,
+    },
+    stats: Stats::new(),
+    on_corruption: None,
+}));
+unsafe impl Send for AllocTracker {}
+unsafe impl Sync for AllocTracker {}
+
+/// Cap the bytes allocated at once, headers not counted. Blocks already over it stay put; only new
+/// allocations fail
+pub fn set_budget(bytes: usize) {
+    unsafe { (*ALLOCATED.0.get()).budget = bytes };
+}
+
+/// Have `hook` called as soon as a block turns up corrupted, while whatever freed or reallocated
+/// it is still on the stack. Only ever happens with the `heap-guard` feature
+pub fn on_corruption(hook: fn(Corruption)) {
+    unsafe { (*ALLOCATED.0.get()).on_corruption = Some(hook) };
+}
+
+pub fn stats() -> Stats {
+    let tracker = unsafe { &*ALLOCATED.0.get() };
+    Stats {
+        pooled: tracker.pool.bytes,
+        ..tracker.stats
+    }
+}
+
+/// Hand every pooled (and quarantined) block back to the heap, e.g. once a video's closed and the
+/// next one's frames are likely a different size
+pub fn trim() {
+    unsafe { (*ALLOCATED.0.get()).release() };
+}
+
+/// Statistics, the size histogram and every block still allocated, e.g. once a file's been closed
+/// and everything ffmpeg had open for it should be gone
+pub fn report(out: &mut impl fmt::Write) -> fmt::Result {
+    let tracker = unsafe { &*ALLOCATED.0.get() };
+    writeln!(out, "{}", stats())?;
+    if let Some(ptr) = tracker.stats.last_double_free {
+        writeln!(out, "Last double free at {ptr:#x}")?;
+    }
+    if let Some(corruption) = tracker.stats.last_corruption {
+        writeln!(out, "Last corrupted block at {corruption}")?;
+    }
+
+    writeln!(out, "Allocations by size:")?;
+    for (class, &count) in tracker.stats.size_classes.iter().enumerate() {
+        if count > 0 {
+            writeln!(out, "  <= {:>8}: {count}", 1usize << class)?;
+        }
+    }
+
+    writeln!(out, "Still allocated:")?;
+    let mut header = tracker.head;
+    while let Some(live) = unsafe { header.as_ref() } {
+        writeln!(
+            out,
+            "  {:?}: {} bytes, align {}",
+            unsafe { ptr_of(header) },
+            live.size,
+            live.align
+        )?;
+        header = live.next;
+    }
+    Ok(())
+}
+
+/// Average time for a malloc/free pair over `rounds` of churn shaped like decoding: packet to
+/// frame sized blocks, aligned for SIMD, freed roughly in the order they came. `now` is any
+/// monotonic clock
+pub fn benchmark(rounds: u32, now: impl Fn() -> Duration) -> Duration {
+    const LIVE_BLOCKS: usize = 64;
+    let mut blocks = [null_mut(); LIVE_BLOCKS];
+    // Fixed seed, so runs compare
+    let mut seed = 0x2545_f491u32;
+
+    let start = now();
+    for round in 0..rounds as usize {
+        seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+        let size = (16 << ((seed >> 28) % 12)) + (seed & 0xff) as usize;
+        let align = 8 << ((seed >> 24) % 4);
+        unsafe {
+            let slot = &mut blocks[round % LIVE_BLOCKS];
+            vexide_free(*slot);
+            *slot = vexide_memalign(align, size);
+        }
+    }
+    let elapsed = now() - start;
+
+    for block in blocks {
+        unsafe { vexide_free(block) };
+    }
+    elapsed / rounds.max(1)
+}
+
+/// Null if over budget or the global allocator is out of memory
+///
+/// # Safety
+/// `layout` has to come from [`block_layout`] for `size`
+unsafe fn allocate(layout: Layout, size: usize) -> *mut c_void {
+    unsafe {
+        let tracker = &mut *ALLOCATED.0.get();
+        let pooled = if layout.size() >= POOL_MIN {
+            let pooled = tracker.pool.take(layout);
+            match pooled {
+                Some(_) => tracker.stats.pool_hits += 1,
+                None => tracker.stats.pool_misses += 1,
+            }
+            pooled
+        } else {
+            None
+        };
+
+        let header = match pooled {
+            Some(header) => header,
+            None => {
+                if !tracker.fits(size) {
+                    return null_mut();
+                }
+                let base = tracker.heap_alloc(layout);
+                if base.is_null() {
+                    return null_mut();
+                }
+                header_of(
+                    base.add(header_space(layout.align()).unwrap_unchecked())
+                        .cast(),
+                )
+            }
+        };
+        header.write(Header {
+            size,
+            align: layout.align(),
+            prev: null_mut(),
+            next: null_mut(),
+            magic: LIVE,
+        });
+        write_guards(header);
+
+        tracker.link(header);
+        tracker.stats.allocated(size);
+        ptr_of(header)
+    }
+}
+
+/// # Safety
+/// Null if out of memory
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn vexide_malloc(size: c_size_t) -> *mut c_void {
+    unsafe { vexide_memalign(MIN_ALIGN, size) }
+}
+
+/// # Safety
+/// `ptr` has to be null or a live block from one of these functions (see [`Stats::double_frees`]
+/// for what happens otherwise). Null if out of memory, leaving `ptr` as it was
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn vexide_realloc(ptr: *mut c_void, size: c_size_t) -> *mut c_void {
+    if ptr.is_null() {
+        return unsafe { vexide_malloc(size) };
+    }
+    if size == 0 {
+        unsafe { vexide_free(ptr) };
+        return null_mut();
+    }
+
+    unsafe {
+        let tracker = &mut *ALLOCATED.0.get();
+        let header = header_of(ptr);
+        if (*header).magic != LIVE {
+            tracker.double_free(ptr);
+            return null_mut();
+        }
+
+        tracker.verify(header);
+        let old_size = (*header).size;
+        let layout = layout_of(header);
+        let Some(new_layout) = block_layout(size, layout.align()) else {
+            return null_mut();
+        };
+        if cfg!(feature = "heap-guard") {
+            // Always move, so the old block goes through quarantine like any other freed one
+            let new_ptr = allocate(new_layout, size);
+            if !new_ptr.is_null() {
+                ptr.cast::<u8>()
+                    .copy_to_nonoverlapping(new_ptr.cast(), old_size.min(size));
+                (*ALLOCATED.0.get()).free(header);
+            }
+            return new_ptr;
+        }
+        if !tracker.fits(size.saturating_sub(old_size)) {
+            return null_mut();
+        }
+
+        // The header moves along with the data, so it has to leave the list while it does
+        tracker.unlink(header);
+        let offset = header_space(layout.align()).unwrap_unchecked();
+        let base = ptr.cast::<u8>().sub(offset);
+        let mut new_base = alloc::alloc::realloc(base, layout, new_layout.size());
+        if new_base.is_null() && tracker.release() {
+            new_base = alloc::alloc::realloc(base, layout, new_layout.size());
+        }
+        if new_base.is_null() {
+            tracker.link(header);
+            tracker.stats.failed += 1;
+            return null_mut();
+        }
+
+        let new_ptr: *mut c_void = new_base.add(offset).cast();
+        let new_header = header_of(new_ptr);
+        (*new_header).size = size;
+        write_guards(new_header);
+        tracker.link(new_header);
+        tracker.stats.freed(old_size);
+        tracker.stats.allocated(size);
+        new_ptr
+    }
+}
+
+/// # Safety
+/// `ptr` has to be null or a live block from one of these functions. Freeing it twice only gets
+/// caught while the block is pooled or quarantined; see [`Stats::double_frees`]
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn vexide_free(ptr: *mut c_void) {
+    if ptr.is_null() {
+        return; // Early exit for nullptr
+    }
+
+    unsafe {
+        let tracker = &mut *ALLOCATED.0.get();
+        let header = header_of(ptr);
+        if (*header).magic != LIVE {
+            tracker.double_free(ptr);
+            return;
+        }
+
+        tracker.verify(header);
+        tracker.free(header);
+    }
+}
+
+/// # Safety
+/// Null if out of memory, or `align` isn't a power of two
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn vexide_memalign(align: c_size_t, size: c_size_t) -> *mut c_void {
+    match block_layout(size, align) {
+        Some(layout) => unsafe { allocate(layout, size) },
+        None => null_mut(),
+    }
+}
+
+/// # Safety
+/// Just is
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn vexide_posix_memalign(
+    ptr: *mut *mut c_void,
+    align: c_size_t,
+    size: c_size_t,
+) -> c_int {
+    let Some(layout) = block_layout(size, align) else {
+        return 22; // EINVAL
+    };
+
+    unsafe {
+        let alloc_ptr = allocate(layout, size);
+        if alloc_ptr.is_null() {
+            12 // ENOMEM
+        } else {
+            ptr.write(alloc_ptr);
+            0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{string::String, vec::Vec};
+    use std::sync::{Mutex, MutexGuard, PoisonError};
+
+    use super::*;
+
+    static LOCK: Mutex<()> = Mutex::new(());
+
+    /// Tests share the one tracker, so they take turns, each starting from nothing allocated
+    fn fresh() -> MutexGuard<'static, ()> {
+        let guard = LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+        let tracker = unsafe { &mut *ALLOCATED.0.get() };
+        tracker.release();
+        // Anything a failed test left behind is leaked
+        tracker.head = null_mut();
+        tracker.budget = usize::MAX;
+        tracker.stats = Stats::new();
+        tracker.on_corruption = None;
+        guard
+    }
+
+    #[test]
+    fn counts_blocks() {
+        let _guard = fresh();
+        unsafe {
+            let small = vexide_malloc(100);
+            let big = vexide_malloc(3000);
+            let aligned = vexide_memalign(64, 50);
+
+            let counts = stats();
+            assert_eq!((counts.current, counts.peak, counts.live), (3150, 3150, 3));
+            assert_eq!(
+                (counts.allocations, counts.frees, counts.largest),
+                (3, 0, 3000)
+            );
+
+            vexide_free(big);
+            let counts = stats();
+            assert_eq!((counts.current, counts.peak, counts.live), (150, 3150, 2));
+            assert_eq!(counts.frees, 1);
+
+            // Counts as freeing the old block & allocating the new one. With `heap-guard` it always
+            // moves, so both are live for a moment
+            let small = vexide_realloc(small, 5000);
+            let counts = stats();
+            let peak = if cfg!(feature = "heap-guard") {
+                5150
+            } else {
+                5050
+            };
+            assert_eq!((counts.current, counts.peak, counts.live), (5050, peak, 2));
+            assert_eq!(
+                (counts.allocations, counts.frees, counts.largest),
+                (4, 2, 5000)
+            );
+
+            vexide_free(small);
+            vexide_free(aligned);
+            let counts = stats();
+            assert_eq!((counts.current, counts.peak, counts.live), (0, peak, 0));
+            assert_eq!(
+                (counts.failed, counts.double_frees, counts.corrupted),
+                (0, 0, 0)
+            );
+        }
+    }
+
+    #[test]
+    fn size_histogram() {
+        assert_eq!(size_class(0), 0);
+        assert_eq!(size_class(1), 0);
+        assert_eq!(size_class(2), 1);
+        assert_eq!(size_class(16), 4);
+        assert_eq!(size_class(17), 5);
+        assert_eq!(size_class(1 << 22), 22);
+        assert_eq!(size_class(usize::MAX), SIZE_CLASSES - 1);
+
+        let _guard = fresh();
+        unsafe {
+            for size in [9, 16, 1025, 3, 16] {
+                vexide_free(vexide_malloc(size));
+            }
+        }
+        let mut expected = [0; SIZE_CLASSES];
+        expected[2] = 1;
+        expected[4] = 3;
+        expected[11] = 1;
+        assert_eq!(stats().size_classes, expected);
+    }
+
+    #[test]
+    fn counts_double_frees() {
+        let _guard = fresh();
+        unsafe {
+            // Big enough to get pooled, so the header's still there to check
+            let ptr = vexide_malloc(64 * 1024);
+            vexide_free(ptr);
+            vexide_free(ptr);
+            assert_eq!(vexide_realloc(ptr, 10), null_mut());
+
+            let counts = stats();
+            assert_eq!((counts.double_frees, counts.frees), (2, 1));
+            assert_eq!(counts.last_double_free, Some(ptr as usize));
+            assert_eq!((counts.current, counts.live), (0, 0));
+        }
+    }
+
+    #[cfg(feature = "heap-guard")]
+    #[test]
+    fn quarantine_catches_double_frees() {
+        let _guard = fresh();
+        unsafe {
+            // Too small to pool, but held back from the heap all the same
+            let ptr = vexide_malloc(100);
+            vexide_free(ptr);
+            vexide_free(ptr);
+            assert_eq!(vexide_realloc(ptr, 10), null_mut());
+            assert_eq!(stats().double_frees, 2);
+
+            // realloc always moves, leaving the old block in quarantine too
+            let ptr = vexide_malloc(100);
+            let moved = vexide_realloc(ptr, 200);
+            assert_ne!(moved, ptr);
+            vexide_free(ptr);
+            assert_eq!(stats().double_frees, 3);
+            vexide_free(moved);
+
+            // Oldest blocks go back to the heap first
+            let blocks: Vec<_> = (0..QUARANTINE_BLOCKS + 10)
+                .map(|_| vexide_malloc(1000))
+                .collect();
+            blocks.into_iter().for_each(|block| vexide_free(block));
+            let tracker = &*ALLOCATED.0.get();
+            assert_eq!(tracker.quarantine.len, QUARANTINE_BLOCKS);
+            assert!(tracker.quarantine.bytes <= QUARANTINE_BYTES);
+        }
+
+        trim();
+        let tracker = unsafe { &*ALLOCATED.0.get() };
+        assert_eq!((tracker.quarantine.len, tracker.quarantine.bytes), (0, 0));
+    }
+
+    #[test]
+    fn reports_live_blocks() {
+        let _guard = fresh();
+        let mut out = String::new();
+        unsafe {
+            let freed = vexide_malloc(300);
+            let live = vexide_memalign(32, 100);
+            vexide_free(freed);
+
+            report(&mut out).unwrap();
+            assert!(out.starts_with(&std::format!("{}\n", stats())));
+            assert!(out.contains("Allocations by size:\n  <=      128: 1\n  <=      512: 1\n"));
+            assert!(out.ends_with(&std::format!(
+                "Still allocated:\n  {live:?}: 100 bytes, align 32\n"
+            )));
+            assert!(!out.contains(&std::format!("{freed:?}")));
+
+            vexide_free(live);
+        }
+
+        out.clear();
+        report(&mut out).unwrap();
+        assert!(out.ends_with("Still allocated:\n"));
+    }
+
+    /// Fill `len` bytes at `ptr` with a pattern that differs per byte
+    unsafe fn fill(ptr: *mut c_void, len: usize) {
+        for i in 0..len {
+            unsafe { ptr.cast::<u8>().add(i).write(i as u8 ^ 0x5a) };
+        }
+    }
+
+    unsafe fn filled(ptr: *mut c_void, len: usize) -> bool {
+        (0..len).all(|i| unsafe { ptr.cast::<u8>().add(i).read() } == i as u8 ^ 0x5a)
+    }
+
+    #[test]
+    fn realloc_keeps_contents() {
+        let _guard = fresh();
+        unsafe {
+            let mut ptr = vexide_malloc(100);
+            let mut old_size = 100;
+            fill(ptr, old_size);
+            // Through small & pooled sizes, up & down
+            for size in [200, 40_000, 100_000, 50, 30_000, 30] {
+                ptr = vexide_realloc(ptr, size);
+                assert!(!ptr.is_null());
+                assert!(filled(ptr, old_size.min(size)), "realloc to {size}");
+                assert_eq!(stats().current, size);
+                fill(ptr, size);
+                old_size = size;
+            }
+
+            // Alignment survives too
+            let aligned = vexide_memalign(64, 10);
+            let aligned = vexide_realloc(aligned, 10_000);
+            assert_eq!(aligned as usize % 64, 0);
+
+            vexide_free(ptr);
+            vexide_free(aligned);
+        }
+        assert_eq!(stats().live, 0);
+    }
+
+    #[test]
+    fn alignment() {
+        let _guard = fresh();
+        unsafe {
+            for size in [1, 7, 100, 5000, 20_000] {
+                let ptr = vexide_malloc(size);
+                assert_eq!(ptr as usize % MIN_ALIGN, 0);
+                vexide_free(ptr);
+
+                for align in [1, 2, 4, 8, 16, 32, 64] {
+                    let ptr = vexide_memalign(align, size);
+                    assert_eq!(
+                        ptr as usize % align.max(MIN_ALIGN),
+                        0,
+                        "memalign({align}, {size})"
+                    );
+                    fill(ptr, size);
+                    vexide_free(ptr);
+
+                    let mut ptr = null_mut();
+                    assert_eq!(vexide_posix_memalign(&mut ptr, align, size), 0);
+                    assert_eq!(
+                        ptr as usize % align.max(MIN_ALIGN),
+                        0,
+                        "posix_memalign({align}, {size})"
+                    );
+                    fill(ptr, size);
+                    vexide_free(ptr);
+                }
+            }
+        }
+        assert_eq!(stats().live, 0);
+    }
+
+    #[test]
+    fn null_and_zero_size() {
+        let _guard = fresh();
+        unsafe {
+            vexide_free(null_mut());
+
+            // Zero-sized blocks are real blocks that need freeing
+            let empty = vexide_malloc(0);
+            assert!(!empty.is_null());
+            assert_eq!(stats().live, 1);
+
+            // realloc(NULL, n) is malloc(n); realloc(ptr, 0) frees
+            let ptr = vexide_realloc(null_mut(), 10);
+            assert!(!ptr.is_null());
+            assert_eq!(vexide_realloc(ptr, 0), null_mut());
+            assert_eq!(vexide_realloc(empty, 0), null_mut());
+
+            let counts = stats();
+            assert_eq!((counts.live, counts.current, counts.frees), (0, 0, 2));
+            assert_eq!(counts.double_frees, 0);
+        }
+    }
+
+    #[test]
+    fn rejects_bad_alignment() {
+        let _guard = fresh();
+        unsafe {
+            for align in [0, 3, 12, 48, 100] {
+                assert_eq!(vexide_memalign(align, 16), null_mut(), "memalign({align})");
+
+                let mut ptr = null_mut();
+                assert_eq!(
+                    vexide_posix_memalign(&mut ptr, align, 16),
+                    22,
+                    "posix_memalign({align})"
+                );
+                assert_eq!(ptr, null_mut());
+            }
+            // Too big to even describe
+            assert_eq!(vexide_malloc(usize::MAX - 8), null_mut());
+        }
+        assert_eq!(stats().allocations, 0);
+    }
+
+    #[test]
+    fn pool_serves_long_decode() {
+        let _guard = fresh();
+        // 480x270 4:2:0 planes, SIMD aligned, with a handful of frames kept for reference & the
+        // odd packet in between
+        let planes = [480 * 270 + 64, 240 * 135 + 64, 240 * 135 + 64];
+        let mut frames: Vec<[*mut c_void; 3]> = Vec::new();
+        let mut seed = 1u32;
+        unsafe {
+            for _ in 0..20_000 {
+                seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                let packet = vexide_malloc(1000 + (seed >> 20) as usize);
+                frames.push(planes.map(|size| {
+                    let mut plane = null_mut();
+                    assert_eq!(vexide_posix_memalign(&mut plane, 64, size), 0);
+                    plane
+                }));
+                if frames.len() > 5 {
+                    frames
+                        .remove(0)
+                        .into_iter()
+                        .for_each(|plane| vexide_free(plane));
+                }
+                vexide_free(packet);
+            }
+            for plane in frames.into_iter().flatten() {
+                vexide_free(plane);
+            }
+        }
+
+        let counts = stats();
+        // Only the first few frames' worth of planes come from the heap
+        assert_eq!(counts.pool_misses, 6 * 3);
+        assert_eq!(counts.pool_hit_rate(), 99);
+        assert_eq!((counts.current, counts.live), (0, 0));
+        assert!(counts.pooled > 0 && counts.pooled <= POOL_BYTES);
+
+        trim();
+        assert_eq!(stats().pooled, 0);
+    }
+
+    #[test]
+    fn pool_limits() {
+        let _guard = fresh();
+        let pooled_size = |size| block_layout(size, MIN_ALIGN).unwrap().size();
+        unsafe {
+            // More sizes than classes: the ones freed last don't fit
+            let sizes: Vec<usize> = (0..POOL_CLASSES + 2).map(|i| 20_000 + i * 1000).collect();
+            let blocks: Vec<_> = sizes.iter().map(|&size| vexide_malloc(size)).collect();
+            blocks.into_iter().for_each(|block| vexide_free(block));
+            let expected: usize = sizes[..POOL_CLASSES]
+                .iter()
+                .map(|&size| pooled_size(size))
+                .sum();
+            assert_eq!(stats().pooled, expected);
+
+            let before = stats();
+            let blocks: Vec<_> = sizes.iter().map(|&size| vexide_malloc(size)).collect();
+            let counts = stats();
+            assert_eq!(counts.pool_hits - before.pool_hits, POOL_CLASSES as u64);
+            assert_eq!(counts.pool_misses - before.pool_misses, 2);
+            assert_eq!(counts.pooled, 0);
+            blocks.into_iter().for_each(|block| vexide_free(block));
+            trim();
+
+            // More bytes than the pool holds
+            let size = 1024 * 1024;
+            let fits = POOL_BYTES / pooled_size(size);
+            let blocks: Vec<_> = (0..fits + 2).map(|_| vexide_malloc(size)).collect();
+            blocks.into_iter().for_each(|block| vexide_free(block));
+            assert_eq!(stats().pooled, fits * pooled_size(size));
+            assert!(stats().pooled <= POOL_BYTES);
+
+            // Small blocks never get pooled
+            vexide_free(vexide_malloc(1000));
+            assert_eq!(stats().pooled, fits * pooled_size(size));
+        }
+        trim();
+    }
+
+    #[test]
+    fn budget_drains_pool() {
+        let _guard = fresh();
+        unsafe {
+            vexide_free(vexide_malloc(200_000));
+            assert!(stats().pooled > 0);
+
+            // Pooled bytes count against the budget, but get let go of rather than failing
+            set_budget(150_000);
+            let block = vexide_malloc(150_000);
+            assert!(!block.is_null());
+            assert_eq!(stats().pooled, 0);
+
+            // Past the budget for real
+            assert_eq!(vexide_malloc(1), null_mut());
+            let mut ptr = null_mut();
+            assert_eq!(vexide_posix_memalign(&mut ptr, 16, 1), 12);
+            assert_eq!(vexide_realloc(block, 150_001), null_mut());
+            assert_eq!(stats().failed, 3);
+
+            // A failed realloc leaves the block as it was
+            assert_eq!((*header_of(block)).size, 150_000);
+            vexide_free(block);
+            assert_eq!(stats().live, 0);
+        }
+        set_budget(usize::MAX);
+        trim();
+    }
+
+    /// Every corruption reported through [`on_corruption`], in order
+    #[cfg(feature = "heap-guard")]
+    static REPORTED: Mutex<Vec<Corruption>> = Mutex::new(Vec::new());
+
+    #[cfg(feature = "heap-guard")]
+    fn reported() -> Vec<Corruption> {
+        core::mem::take(&mut *REPORTED.lock().unwrap())
+    }
+
+    #[cfg(feature = "heap-guard")]
+    #[test]
+    fn catches_corruption() {
+        let _guard = fresh();
+        reported();
+        on_corruption(|corruption| REPORTED.lock().unwrap().push(corruption));
+        let corruption = |ptr: *mut u8, size, underrun, overrun| Corruption {
+            ptr: ptr as usize,
+            size,
+            underrun,
+            overrun,
+        };
+
+        unsafe {
+            // Past the end, caught by free
+            let ptr = vexide_malloc(100).cast::<u8>();
+            ptr.add(100).write(1);
+            vexide_free(ptr.cast());
+            assert_eq!(reported(), [corruption(ptr, 100, false, true)]);
+
+            // In front, caught by realloc, which carries on with fresh canaries
+            let ptr = vexide_memalign(64, 50).cast::<u8>();
+            ptr.sub(1).write(0);
+            let moved = vexide_realloc(ptr.cast(), 5000);
+            assert!(!moved.is_null());
+            assert_eq!(reported(), [corruption(ptr, 50, true, false)]);
+            vexide_free(moved);
+            assert_eq!(reported(), []);
+
+            // Both ends of the same block
+            let ptr = vexide_malloc(30).cast::<u8>();
+            ptr.sub(GUARD).write(0);
+            ptr.add(30 + GUARD - 1).write(0);
+            vexide_free(ptr.cast());
+            assert_eq!(reported(), [corruption(ptr, 30, true, true)]);
+
+            // Anything inside the block is fair game, including after shrinking it
+            let ptr = vexide_malloc(300).cast::<u8>();
+            ptr.write(1);
+            ptr.add(299).write(1);
+            let ptr = vexide_realloc(ptr.cast(), 10).cast::<u8>();
+            ptr.add(9).write(1);
+            vexide_free(ptr.cast());
+            assert_eq!(reported(), []);
+
+            // Pooled blocks get checked on the way into the pool
+            let ptr = vexide_malloc(100_000).cast::<u8>();
+            ptr.add(100_000).write(0);
+            vexide_free(ptr.cast());
+            assert_eq!(reported(), [corruption(ptr, 100_000, false, true)]);
+            // & poisoned, which is still readable since the pool holds on to it
+            assert_eq!(ptr.add(5).read(), POISON);
+        }
+
+        let counts = stats();
+        assert_eq!(counts.corrupted, 4);
+        assert_eq!(
+            counts.last_corruption.map(|corruption| corruption.size),
+            Some(100_000)
+        );
+        let mut out = String::new();
+        report(&mut out).unwrap();
+        assert!(out.contains(", 100000 bytes: written past\n"));
+        trim();
+    }
+}
@@ -0,0 +1,243 @@
+//! Keyframe index of a video stream, so seeks land straight on the keyframe before the target
+//!
+//! Containers without an index of their own get scanned once and the result cached next to the
+//! video as a small text sidecar:
+//!
+//! ```text
+//! keyframes v1
+//! size 48213902          # of the video file, to catch it being replaced
+//! stream 0 1/1000        # index & time base
+//! 0 4521                 # timestamp & byte position, one keyframe per line
+//! 2002 90122
+//! ```
+
+use alloc::{format, string::String, vec::Vec};
+use core::fmt::Write;
+
+const MAGIC: &str = "keyframes v1";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Keyframe {
+    /// In the stream's time base
+    pub timestamp: i64,
+    /// Byte offset of its packet in the file
+    pub position: i64,
+}
+
+/// Which file & stream an index belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Source {
+    pub file_size: u64,
+    pub stream_index: usize,
+    pub time_base: (i32, i32),
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct KeyframeIndex {
+    /// Sorted by timestamp
+    keyframes: Vec<Keyframe>,
+}
+
+impl KeyframeIndex {
+    pub fn new(mut keyframes: Vec<Keyframe>) -> Self {
+        keyframes.sort_unstable_by_key(|keyframe| keyframe.timestamp);
+        keyframes.dedup_by_key(|keyframe| keyframe.timestamp);
+        Self { keyframes }
+    }
+
+    pub fn keyframes(&self) -> &[Keyframe] {
+        &self.keyframes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keyframes.is_empty()
+    }
+
+    /// Last keyframe at or before `timestamp`
+    pub fn at_or_before(&self, timestamp: i64) -> Option<Keyframe> {
+        let after = self
+            .keyframes
+            .partition_point(|keyframe| keyframe.timestamp <= timestamp);
+        after.checked_sub(1).map(|index| self.keyframes[index])
+    }
+
+    /// Whether `to` is ahead of `from` without a keyframe in between, in which case decoding on
+    /// from `from` gets there quicker than seeking back to the keyframe
+    pub fn same_group(&self, from: i64, to: i64) -> bool {
+        from <= to && self.at_or_before(from) == self.at_or_before(to)
+    }
+
+    pub fn to_sidecar(&self, source: Source) -> String {
+        let Source {
+            file_size,
+            stream_index,
+            time_base: (num, den),
+        } = source;
+
+        let mut text = format!("{MAGIC}\nsize {file_size}\nstream {stream_index} {num}/{den}\n");
+        for keyframe in &self.keyframes {
+            // Writing to a String can't fail
+            let _ = writeln!(text, "{} {}", keyframe.timestamp, keyframe.position);
+        }
+        text
+    }
+
+    /// `None` if `text` is garbled or was made for a different file/stream
+    pub fn from_sidecar(text: &str, source: Source) -> Option<Self> {
+        let mut lines = text.lines();
+        if lines.next()? != MAGIC {
+            return None;
+        }
+
+        let file_size = lines.next()?.strip_prefix("size ")?.parse().ok()?;
+        let (stream_index, time_base) = lines.next()?.strip_prefix("stream ")?.split_once(' ')?;
+        let (num, den) = time_base.split_once('/')?;
+        let header = Source {
+            file_size,
+            stream_index: stream_index.parse().ok()?,
+            time_base: (num.parse().ok()?, den.parse().ok()?),
+        };
+        if header != source {
+            return None;
+        }
+
+        let keyframes = lines
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                let (timestamp, position) = line.split_once(' ')?;
+                Some(Keyframe {
+                    timestamp: timestamp.parse().ok()?,
+                    position: position.parse().ok()?,
+                })
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some(Self::new(keyframes))
+    }
+}
+
+/// Where the index for the video at `path` gets cached
+pub fn sidecar_path(path: &str) -> String {
+    format!("{path}.idx")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: Source = Source {
+        file_size: 48_213_902,
+        stream_index: 0,
+        time_base: (1, 1000),
+    };
+
+    fn keyframe(timestamp: i64) -> Keyframe {
+        Keyframe {
+            timestamp,
+            position: timestamp * 10,
+        }
+    }
+
+    /// Keyframes at 0, 2000 & 4000
+    fn index() -> KeyframeIndex {
+        KeyframeIndex::new([4000, 0, 2000, 2000].map(keyframe).to_vec())
+    }
+
+    #[test]
+    fn sorts_and_dedups() {
+        assert_eq!(index().keyframes(), [0, 2000, 4000].map(keyframe));
+        assert!(KeyframeIndex::new(Vec::new()).is_empty());
+    }
+
+    #[test]
+    fn at_or_before() {
+        let index = KeyframeIndex::new([1000, 3000].map(keyframe).to_vec());
+        assert_eq!(index.at_or_before(0), None);
+        assert_eq!(index.at_or_before(999), None);
+        assert_eq!(index.at_or_before(1000), Some(keyframe(1000)));
+        assert_eq!(index.at_or_before(2999), Some(keyframe(1000)));
+        assert_eq!(index.at_or_before(3000), Some(keyframe(3000)));
+        assert_eq!(index.at_or_before(i64::MAX), Some(keyframe(3000)));
+        assert_eq!(KeyframeIndex::default().at_or_before(0), None);
+    }
+
+    #[test]
+    fn same_group() {
+        let index = index();
+        assert!(index.same_group(500, 1999));
+        assert!(index.same_group(2000, 2000));
+        assert!(index.same_group(2000, 3999));
+        // Crossing a keyframe, even onto it
+        assert!(!index.same_group(1999, 2000));
+        assert!(!index.same_group(500, 2500));
+        // Never backwards
+        assert!(!index.same_group(1500, 500));
+        // Past the last keyframe it's one long group
+        assert!(index.same_group(4000, i64::MAX));
+
+        // Before the first keyframe there's nothing to decode on from
+        let late_start = KeyframeIndex::new([1000].map(keyframe).to_vec());
+        assert!(late_start.same_group(0, 999));
+        assert!(!late_start.same_group(0, 1000));
+    }
+
+    #[test]
+    fn sidecar_round_trip() {
+        let index = index();
+        let text = index.to_sidecar(SOURCE);
+        assert_eq!(
+            text,
+            "keyframes v1\nsize 48213902\nstream 0 1/1000\n0 0\n2000 20000\n4000 40000\n"
+        );
+        assert_eq!(KeyframeIndex::from_sidecar(&text, SOURCE), Some(index));
+
+        let empty = KeyframeIndex::default();
+        assert_eq!(
+            KeyframeIndex::from_sidecar(&empty.to_sidecar(SOURCE), SOURCE),
+            Some(empty)
+        );
+    }
+
+    #[test]
+    fn sidecar_for_other_source() {
+        let text = index().to_sidecar(SOURCE);
+        for other in [
+            Source {
+                file_size: 48_213_903,
+                ..SOURCE
+            },
+            Source {
+                stream_index: 1,
+                ..SOURCE
+            },
+            Source {
+                time_base: (1, 90_000),
+                ..SOURCE
+            },
+        ] {
+            assert_eq!(KeyframeIndex::from_sidecar(&text, other), None, "{other:?}");
+        }
+    }
+
+    #[test]
+    fn garbled_sidecar() {
+        for garbled in [
+            "",
+            "keyframes v2\nsize 48213902\nstream 0 1/1000\n",
+            "keyframes v1\nstream 0 1/1000\n",
+            "keyframes v1\nsize 48213902\nstream 0 1000\n",
+            "keyframes v1\nsize 48213902\nstream 0 1/1000\n0\n",
+            "keyframes v1\nsize 48213902\nstream 0 1/1000\n0 x\n",
+        ] {
+            assert_eq!(
+                KeyframeIndex::from_sidecar(garbled, SOURCE),
+                None,
+                "{garbled:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn sidecar_path() {
+        assert_eq!(super::sidecar_path("videos/a.mp4"), "videos/a.mp4.idx");
+    }
+}
@@ -0,0 +1,306 @@
+//! Where on screen a video ends up, and which part of it is visible
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum FitMode {
+    /// Fill the screen, ignoring aspect ratio
+    Stretch,
+    /// Largest size that fits, with black bars
+    #[default]
+    Fit,
+    /// Cover the whole screen, cropping whatever doesn't fit
+    Fill,
+    /// One source pixel per screen pixel, centered & cropped
+    Original,
+}
+
+/// Axis aligned rectangle, in pixels
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Region {
+    pub const fn new(x: u32, y: u32, width: u32, height: u32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    pub const fn area(&self) -> usize {
+        self.width as usize * self.height as usize
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Layout {
+    /// Part of the source frame that's visible, in source pixels
+    pub src: Region,
+    /// Where that part lands on screen
+    pub dst: Region,
+}
+
+impl Layout {
+    /// `sample_aspect` is the source's `sample_aspect_ratio` as `(num, den)`; unknown (`0/x`) is treated as square
+    pub fn compute(
+        mode: FitMode,
+        (src_width, src_height): (u32, u32),
+        sample_aspect: (i32, i32),
+        (screen_width, screen_height): (u32, u32),
+    ) -> Self {
+        let (sar_num, sar_den) = match sample_aspect {
+            (num, den) if num > 0 && den > 0 => (num as u64, den as u64),
+            _ => (1, 1),
+        };
+
+        let (src_w, src_h) = (src_width.max(1) as u64, src_height.max(1) as u64);
+        let (screen_w, screen_h) = (screen_width as u64, screen_height as u64);
+        let full_src = Region::new(0, 0, src_width, src_height);
+
+        // Source size in square pixels is (src_w * sar_num / sar_den) x src_h; keep it as a ratio
+        let display_w = src_w * sar_num;
+        let display_h = src_h * sar_den;
+        let wider_than_screen = display_w * screen_h >= display_h * screen_w;
+
+        match mode {
+            FitMode::Stretch => Self {
+                src: full_src,
+                dst: Region::new(0, 0, screen_width, screen_height),
+            },
+            FitMode::Fit => {
+                let (width, height) = if wider_than_screen {
+                    (screen_w, screen_w * display_h / display_w)
+                } else {
+                    (screen_h * display_w / display_h, screen_h)
+                };
+
+                Self {
+                    src: full_src,
+                    dst: centered(width.max(1), height.max(1), screen_w, screen_h),
+                }
+            }
+            FitMode::Fill => {
+                // Crop the axis that overflows, in source pixels
+                let (crop_w, crop_h) = if wider_than_screen {
+                    (src_w * screen_w * display_h / (screen_h * display_w), src_h)
+                } else {
+                    (src_w, src_h * screen_h * display_w / (screen_w * display_h))
+                };
+
+                Self {
+                    src: centered(crop_w.max(1), crop_h.max(1), src_w, src_h),
+                    dst: Region::new(0, 0, screen_width, screen_height),
+                }
+            }
+            FitMode::Original => {
+                let width = (src_w * sar_num / sar_den).max(1);
+                let height = src_h;
+                let (dst_w, dst_h) = (width.min(screen_w), height.min(screen_h));
+
+                Self {
+                    src: centered(
+                        (src_w * dst_w / width).max(1),
+                        (src_h * dst_h / height).max(1),
+                        src_w,
+                        src_h,
+                    ),
+                    dst: centered(dst_w, dst_h, screen_w, screen_h),
+                }
+            }
+        }
+    }
+
+    /// The same layout for a frame decoded at 1/2^`lowres` of the size it was computed for, which
+    /// is `width` x `height` now. `dst` stays put so the picture doesn't jump; `src` shrinks to
+    /// match, rounding outwards
+    pub fn lowres(self, lowres: u8, (width, height): (u32, u32)) -> Self {
+        let shrink = |start: u32, length: u32, limit: u32| {
+            let end = (start + length).div_ceil(1 << lowres).min(limit);
+            let start = (start >> lowres).min(end.saturating_sub(1));
+            (start, (end - start).max(1))
+        };
+        let (x, width) = shrink(self.src.x, self.src.width, width);
+        let (y, height) = shrink(self.src.y, self.src.height, height);
+
+        Self {
+            src: Region::new(x, y, width, height),
+            dst: self.dst,
+        }
+    }
+}
+
+fn centered(width: u64, height: u64, outer_width: u64, outer_height: u64) -> Region {
+    let width = width.min(outer_width);
+    let height = height.min(outer_height);
+    Region::new(
+        ((outer_width - width) / 2) as u32,
+        ((outer_height - height) / 2) as u32,
+        width as u32,
+        height as u32,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCREEN: (u32, u32) = (480, 240);
+    const SQUARE: (i32, i32) = (1, 1);
+
+    fn layout(mode: FitMode, src: (u32, u32), sample_aspect: (i32, i32)) -> (Region, Region) {
+        let Layout { src, dst } = Layout::compute(mode, src, sample_aspect, SCREEN);
+        (src, dst)
+    }
+
+    #[test]
+    fn stretch() {
+        assert_eq!(
+            layout(FitMode::Stretch, (1920, 1080), SQUARE),
+            (Region::new(0, 0, 1920, 1080), Region::new(0, 0, 480, 240))
+        );
+    }
+
+    #[test]
+    fn fit() {
+        // Pillarboxed
+        assert_eq!(
+            layout(FitMode::Fit, (1920, 1080), SQUARE),
+            (Region::new(0, 0, 1920, 1080), Region::new(27, 0, 426, 240))
+        );
+        // Letterboxed
+        assert_eq!(
+            layout(FitMode::Fit, (1000, 100), SQUARE),
+            (Region::new(0, 0, 1000, 100), Region::new(0, 96, 480, 48))
+        );
+        // Same shape as the screen
+        assert_eq!(
+            layout(FitMode::Fit, (960, 480), SQUARE).1,
+            Region::new(0, 0, 480, 240)
+        );
+    }
+
+    #[test]
+    fn fill_crops() {
+        assert_eq!(
+            layout(FitMode::Fill, (1920, 1080), SQUARE),
+            (Region::new(0, 60, 1920, 960), Region::new(0, 0, 480, 240))
+        );
+        assert_eq!(
+            layout(FitMode::Fill, (1000, 100), SQUARE),
+            (Region::new(400, 0, 200, 100), Region::new(0, 0, 480, 240))
+        );
+    }
+
+    #[test]
+    fn original_is_centered() {
+        assert_eq!(
+            layout(FitMode::Original, (320, 200), SQUARE),
+            (Region::new(0, 0, 320, 200), Region::new(80, 20, 320, 200))
+        );
+        // Too big for the screen, so only the middle shows
+        assert_eq!(
+            layout(FitMode::Original, (1920, 1080), SQUARE),
+            (Region::new(720, 420, 480, 240), Region::new(0, 0, 480, 240))
+        );
+    }
+
+    #[test]
+    fn unknown_sample_aspect_is_square() {
+        for mode in [
+            FitMode::Stretch,
+            FitMode::Fit,
+            FitMode::Fill,
+            FitMode::Original,
+        ] {
+            let square = layout(mode, (720, 480), SQUARE);
+            for unknown in [(0, 0), (0, 1), (1, 0), (-4, 3)] {
+                assert_eq!(
+                    layout(mode, (720, 480), unknown),
+                    square,
+                    "{mode:?} {unknown:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn non_square_samples() {
+        // Anamorphic 16:9 DVD comes out the same shape as square-pixel 16:9
+        assert_eq!(
+            layout(FitMode::Fit, (720, 480), (32, 27)).1,
+            Region::new(27, 0, 426, 240)
+        );
+        assert_eq!(
+            layout(FitMode::Fill, (720, 480), (32, 27)).0,
+            Region::new(0, 27, 720, 426)
+        );
+        // 853 square pixels wide, so less than half the width fits
+        assert_eq!(
+            layout(FitMode::Original, (720, 480), (32, 27)),
+            (Region::new(157, 120, 405, 240), Region::new(0, 0, 480, 240))
+        );
+        // Tall pixels, so square on screen
+        assert_eq!(
+            layout(FitMode::Fit, (480, 240), (1, 2)).1,
+            Region::new(120, 0, 240, 240)
+        );
+    }
+
+    #[test]
+    fn odd_sizes() {
+        assert_eq!(
+            layout(FitMode::Fit, (3, 1), SQUARE).1,
+            Region::new(0, 40, 480, 160)
+        );
+        assert_eq!(
+            layout(FitMode::Fit, (479, 241), SQUARE).1,
+            Region::new(1, 0, 477, 240)
+        );
+        assert_eq!(
+            layout(FitMode::Fill, (479, 241), SQUARE).0,
+            Region::new(0, 1, 479, 239)
+        );
+        // Never collapses to nothing
+        assert_eq!(
+            layout(FitMode::Fit, (10_000, 1), SQUARE).1,
+            Region::new(0, 119, 480, 1)
+        );
+        assert_eq!(
+            layout(FitMode::Fill, (1, 1), SQUARE).0,
+            Region::new(0, 0, 1, 1)
+        );
+        // Empty frames are fitted as if they were one pixel
+        assert_eq!(
+            layout(FitMode::Fit, (0, 0), SQUARE),
+            (Region::new(0, 0, 0, 0), Region::new(120, 0, 240, 240))
+        );
+    }
+
+    #[test]
+    fn lowres_keeps_dst() {
+        let full = Layout::compute(FitMode::Fill, (1920, 1080), SQUARE, SCREEN);
+        assert_eq!(
+            full.lowres(1, (960, 540)),
+            Layout {
+                src: Region::new(0, 30, 960, 480),
+                dst: full.dst,
+            }
+        );
+        assert_eq!(full.lowres(0, (1920, 1080)), full);
+
+        // Rounds outwards, then clamps to the smaller frame
+        let odd = Layout {
+            src: Region::new(3, 5, 7, 9),
+            dst: Region::new(0, 0, 480, 240),
+        };
+        assert_eq!(odd.lowres(1, (10, 10)).src, Region::new(1, 2, 4, 5));
+        assert_eq!(odd.lowres(1, (4, 6)).src, Region::new(1, 2, 3, 4));
+        let whole = Layout::compute(FitMode::Fit, (1922, 1082), SQUARE, SCREEN);
+        assert_eq!(whole.lowres(1, (961, 541)).src, Region::new(0, 0, 961, 541));
+    }
+}
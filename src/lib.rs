@@ -0,0 +1,25 @@
+//! Everything that doesn't need ffmpeg or vexide, so it can be built & tested on the host with
+//! `cargo test-host`
+
+#![feature(c_size_t)]
+#![cfg_attr(target_arch = "arm", feature(stdarch_arm_neon_intrinsics))]
+#![no_std]
+
+extern crate alloc;
+#[cfg(test)]
+extern crate std;
+
+pub mod color;
+pub mod config;
+pub mod controls;
+pub mod ffmpeg_alloc;
+pub mod keyframes;
+pub mod layout;
+pub mod overlay;
+pub mod pixfmt;
+pub mod playlist;
+pub mod quality;
+pub mod queue;
+pub mod render;
+pub mod scale;
+pub mod scheduler;
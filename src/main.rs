@@ -12,21 +12,32 @@ use alloc::{
     borrow::ToOwned,
     boxed::Box,
     collections::{BTreeMap, BTreeSet},
-    string::String,
+    rc::Rc,
+    string::{String, ToString},
     vec::{self, Vec},
 };
 use core::{
-    cell::{SyncUnsafeCell, UnsafeCell},
+    cell::{RefCell, SyncUnsafeCell, UnsafeCell},
     ffi::{CStr, c_int, c_long, c_size_t, c_void},
     pin::Pin,
     time::Duration,
     u8,
 };
 
+use config::{Config, LogLevel, LoopMode};
+use controls::{Buttons, Command};
+use error::PlayerError;
+use layout::Region;
+use player::Repeat;
 use rgb::{Argb, Bgra, ComponentMap, FromSlice};
+use scheduler::Clock;
 use vexide::{
-    devices::display::Rect,
-    fs::File,
+    devices::{
+        controller::Controller,
+        display::{Font, FontFamily, FontSize, Rect, Text},
+        math::Point2,
+        rgb::Rgb,
+    },
     prelude::*,
     startup::banner::themes::BannerTheme,
     sync::{LazyLock, Mutex},
@@ -45,165 +56,16 @@ pub mod ffmpeg {
     include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
 }
 
-mod ffmpeg_alloc {
-    use alloc::collections::BTreeMap;
-    use core::{
-        alloc::Layout,
-        cell::UnsafeCell,
-        ffi::{c_int, c_size_t, c_void},
-    };
-
-    use vexide::io::println;
-
-    struct AllocTracker(UnsafeCell<BTreeMap<*mut c_void, Layout>>);
-    static ALLOCATED: AllocTracker = AllocTracker(UnsafeCell::new(BTreeMap::new()));
-    unsafe impl Send for AllocTracker {}
-    unsafe impl Sync for AllocTracker {}
-
-    /// # Safety
-    /// Panics on Out of Memory
-    #[unsafe(no_mangle)]
-    pub unsafe extern "C" fn vexide_malloc(size: c_size_t) -> *mut c_void {
-        unsafe { vexide_memalign(1, size) }
-    }
-
-    /// # Safety
-    /// Panics on Out of Memory
-    #[unsafe(no_mangle)]
-    pub unsafe extern "C" fn vexide_realloc(ptr: *mut c_void, size: c_size_t) -> *mut c_void {
-        unsafe {
-            match (*ALLOCATED.0.get()).get(&ptr) {
-                Some(layout) => {
-                    // Existing alloc; Let's realloc and move data
-                    if size == 0 {
-                        vexide_free(ptr);
-                        return core::ptr::null_mut();
-                    }
-
-                    let new_ptr = alloc::alloc::realloc(ptr.cast(), *layout, size).cast();
-                    (*ALLOCATED.0.get()).remove(&ptr);
-                    (*ALLOCATED.0.get()).insert(
-                        new_ptr,
-                        Layout::from_size_align_unchecked(size, layout.align()),
-                    );
-
-                    new_ptr
-                }
-                None => {
-                    // New allocation
-                    vexide_memalign(1, size)
-                }
-            }
-        }
-    }
-
-    /// # Safety
-    /// Only deallocs pointer we know
-    #[unsafe(no_mangle)]
-    pub unsafe extern "C" fn vexide_free(ptr: *mut c_void) {
-        if ptr.is_null() {
-            return; // Early exit for nullptr
-        }
-
-        //println!("Freed ptr {ptr:?}");
-        unsafe {
-            let Some(layout) = (*ALLOCATED.0.get()).remove(&ptr) else {
-                vexide::io::println!("Double free at {ptr:?}");
-                return;
-            };
-            alloc::alloc::dealloc(ptr.cast(), layout);
-        }
-    }
-
-    /// # Safety
-    /// Panics on Out of Memory
-    #[unsafe(no_mangle)]
-    pub unsafe extern "C" fn vexide_memalign(align: c_size_t, size: c_size_t) -> *mut c_void {
-        let layout = Layout::from_size_align(size, align).expect("Invalid mem layout");
-        unsafe {
-            let ptr: *mut c_void = alloc::alloc::alloc(layout).cast();
-            if ptr.is_null() {
-                alloc::alloc::handle_alloc_error(layout);
-            }
-            //println!("Allocated {size} at {ptr:?}");
-
-            (*ALLOCATED.0.get()).insert(ptr, layout);
-            ptr
-        }
-    }
-    /// # Safety
-    /// Just is
-    #[unsafe(no_mangle)]
-    pub unsafe extern "C" fn vexide_posix_memalign(
-        ptr: *mut *mut c_void,
-        align: c_size_t,
-        size: c_size_t,
-    ) -> c_int {
-        let Ok(layout) = Layout::from_size_align(size, align) else {
-            return 22; // EINVAL
-        };
-
-        unsafe {
-            let alloc_ptr: *mut c_void = alloc::alloc::alloc(layout).cast();
-            if alloc_ptr.is_null() {
-                12 // ENOMEM
-            } else {
-                (*ALLOCATED.0.get()).insert(alloc_ptr, layout);
-                ptr.write(alloc_ptr);
-                0
-            }
-        }
-    }
-}
-
-const AVERROR_EOF: i32 =
-    -(((b'E' as u32) | (b'O' as u32) << 8 | (b'F' as u32) << 16 | (b' ' as u32) << 24) as i32);
-
-#[unsafe(no_mangle)]
-unsafe extern "C" fn vexide_file_read(context: *mut c_void, ptr: *mut u8, size: i32) -> i32 {
-    let mut file: Box<File> = unsafe { Box::from_raw(context.cast()) };
-
-    // Read from current position into
-    let buf = core::ptr::slice_from_raw_parts_mut(ptr, size as usize);
-    let read = unsafe { file.read(&mut *buf).expect("Failed to read file") };
-
-    core::mem::forget(file); // Don't drop the file
-    if read == 0 && size != 0 {
-        AVERROR_EOF
-    } else {
-        read as i32
-    }
-}
-
-unsafe extern "C" fn vexide_file_seek(context: *mut c_void, offset: i64, whence: c_int) -> i64 {
-    let mut file: Box<File> = unsafe { Box::from_raw(context.cast()) };
-
-    const SEEK_SET: c_int = 0;
-    const SEEK_CUR: c_int = 1;
-    const SEEK_END: c_int = 2;
-    const SEEK_SIZE: c_int = ffmpeg::AVSEEK_SIZE as c_int;
-
-    let offset = match whence {
-        SEEK_SET => vexide::io::SeekFrom::Start(offset as u64),
-        SEEK_CUR => vexide::io::SeekFrom::Current(offset),
-        SEEK_END => vexide::io::SeekFrom::End(offset),
-        SEEK_SIZE => {
-            let size = file
-                .metadata()
-                .expect("shitface")
-                .len()
-                .expect("missing length");
-            core::mem::forget(file);
-            return size as i64;
-        }
-        whence => panic!("Invalid seek position {whence}"),
-    };
+mod error;
+mod media;
+mod menu;
+mod pipeline;
+mod player;
 
-    let new_offset = file.seek(offset).expect("shitface");
-
-    core::mem::forget(file);
-    new_offset as i64
-}
+use videoplayer::{
+    color, config, controls, ffmpeg_alloc, keyframes, layout, overlay, pixfmt, playlist, quality,
+    queue, render, scale, scheduler,
+};
 
 #[unsafe(no_mangle)]
 extern "C" fn __paritysi2(mut x: c_int) -> c_int {
@@ -512,6 +374,14 @@ extern "C" fn pthread_cond_wait(
     unimplemented!("pthread_cond_wait")
 }
 
+struct SystemClock(Instant);
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.0.elapsed()
+    }
+}
+
 unsafe extern "C" {
     static __heap_start: u8;
     static __heap_end: u8;
@@ -530,352 +400,502 @@ async fn main(mut peripherals: Peripherals) {
         __libc_init_array();
     }
 
-    println!(
-        "Usable memory range: {:?}-{:?}",
-        core::ptr::addr_of!(__heap_start),
-        core::ptr::addr_of!(__heap_end)
-    );
+    let heap_start = core::ptr::addr_of!(__heap_start);
+    let heap_end = core::ptr::addr_of!(__heap_end);
+    let heap_size = heap_end as usize - heap_start as usize;
+    println!("Usable memory range: {heap_start:?}-{heap_end:?} ({heap_size} bytes)");
 
-    let video_file = vexide::fs::File::open("rickroll.webm").expect("shitface");
-    println!("Opened file");
+    let display = &mut peripherals.display;
+    let controller = &peripherals.primary_controller;
 
-    unsafe {
-        let mut av_context = ffmpeg::avformat_alloc_context();
-        (*av_context).debug = !0;
-        println!("AVFormat Alloc");
-
-        let avio_buffer = ffmpeg::av_malloc(1024 * 64).cast(); // 64Kb buffer
-        let mut avio_ctx = ffmpeg::avio_alloc_context(
-            avio_buffer,
-            1024 * 64,
-            0,
-            Box::into_raw(Box::new(video_file)).cast(),
-            Some(vexide_file_read),
-            None, // Dont think this needs to be set
-            Some(vexide_file_seek),
-        );
-        (*av_context).pb = avio_ctx;
-        println!("AVIO Alloc");
-
-        let result = ffmpeg::avformat_open_input(
-            &mut av_context as *mut _,
-            core::ptr::null_mut(),
-            core::ptr::null_mut(),
-            core::ptr::null_mut(),
-        );
-        if result != 0 {
-            let mut str = [0u8; 1024];
-            ffmpeg::av_strerror(result, &mut str as *mut _, 1024);
-            println!(
-                "Failed to open input: {}",
-                CStr::from_bytes_until_nul(&str)
-                    .expect("shitface")
-                    .to_str()
-                    .expect("shitface")
-            );
-            return;
+    let config = match load_config() {
+        Ok(config) => config,
+        Err(err) => {
+            report_error(display, controller, &err, None).await;
+            Config::default()
         }
-        println!("AVFormat Open Input");
-
-        let result = ffmpeg::avformat_find_stream_info(av_context, core::ptr::null_mut());
-        if result < 0 {
-            let mut str = [0u8; 1024];
-            ffmpeg::av_strerror(result, &mut str as *mut _, 1024);
-            println!(
-                "Failed to find stream info: {}",
-                CStr::from_bytes_until_nul(&str)
-                    .expect("shitface")
-                    .to_str()
-                    .expect("shitface")
-            );
-            return;
-        }
-        println!("AVFormat Find Stream Info");
-
-        let mut codec: *const ffmpeg::AVCodec = core::ptr::null();
-        let stream_index = ffmpeg::av_find_best_stream(
-            av_context,
-            ffmpeg::AVMEDIA_TYPE_VIDEO,
-            -1,
-            -1,
-            &mut codec as *mut *const _,
-            0,
-        );
-        if stream_index < 0 {
-            let mut str = [0u8; 1024];
-            ffmpeg::av_strerror(stream_index, &mut str as *mut _, 1024);
-            println!(
-                "Failed to find best stream/decoder: {}",
-                CStr::from_bytes_until_nul(&str)
-                    .expect("shitface")
-                    .to_str()
-                    .expect("shitface")
-            );
-            return;
-        }
-        let stream = *(*av_context).streams.add(stream_index as usize);
-        println!("Found best stream+decoder");
+    };
+    set_log_level(config.log_level);
+
+    let budget = config.memory_budget.unwrap_or(heap_size / 4 * 3);
+    println!("ffmpeg may allocate up to {budget} bytes");
+    ffmpeg_alloc::set_budget(budget);
+    // Straight away, since the report only gets written once the file's done, if it gets that far
+    ffmpeg_alloc::on_corruption(|corruption| println!("ffmpeg heap corrupted: {corruption}"));
+
+    if config.alloc_benchmark {
+        let start = Instant::now();
+        let per_pair = ffmpeg_alloc::benchmark(100_000, || start.elapsed());
+        println!("ffmpeg_alloc: {per_pair:?} per malloc/free");
+    }
 
-        let parser = ffmpeg::av_parser_init((*codec).id as c_int);
-        if parser.is_null() {
-            println!("Failed to create parser");
-            return;
-        }
+    // The configured file gets played once on startup, after that it's the menu
+    let mut startup_file = config.file.clone();
+    loop {
+        let chosen = match startup_file.take() {
+            Some(path) => {
+                menu::load_playlist(&path, config.loop_mode == LoopMode::All, config.shuffle)
+            }
+            None => menu::choose(display, controller, &config).await,
+        };
+        let mut playlist = match chosen {
+            Ok(playlist) => playlist,
+            Err(err) => {
+                report_error(display, controller, &err, None).await;
+                continue;
+            }
+        };
 
-        let codec_ctx = ffmpeg::avcodec_alloc_context3(codec);
-        if codec_ctx.is_null() {
-            println!("Failed to create codec context");
-            return;
+        let mut current = playlist.current().map(ToOwned::to_owned);
+        while let Some(path) = current {
+            println!("Playing {path}");
+            let next = match play(display, controller, &path, &config).await {
+                Ok(Outcome::Finished) => playlist.advance(false),
+                Ok(Outcome::Next) => playlist.advance(true),
+                Ok(Outcome::Previous) => playlist.back(),
+                Ok(Outcome::Stopped) => None,
+                Err(err) => {
+                    // Nobody might be watching, so don't hold up the rest of the playlist
+                    report_error(display, controller, &err, Some(ERROR_TIMEOUT)).await;
+                    playlist.advance(false)
+                }
+            };
+            ffmpeg_alloc::trim();
+            report_allocations(&config);
+            current = next.map(ToOwned::to_owned);
         }
+    }
+}
 
-        let result = ffmpeg::avcodec_parameters_to_context(codec_ctx, (*stream).codecpar);
-        if result < 0 {
-            println!("Failed to copy parameters to context");
-            return;
-        }
+/// Settings file on the SD card; everything stays at its default without one
+const CONFIG_PATH: &str = "player.toml";
 
-        let result = ffmpeg::avcodec_open2(codec_ctx, codec, core::ptr::null_mut());
-        if result < 0 {
-            println!("Failed to open codec stream");
-            return;
+fn load_config() -> Result<Config, PlayerError> {
+    let text = match vexide::fs::read_to_string(CONFIG_PATH) {
+        Ok(text) => text,
+        Err(err) if err.kind() == vexide::io::ErrorKind::NotFound => {
+            println!("No {CONFIG_PATH}, using defaults");
+            return Ok(Config::default());
         }
+        Err(err) => return Err(err.into()),
+    };
+    Ok(config::parse(&text)?)
+}
 
-        let frame = ffmpeg::av_frame_alloc();
-        let packet = ffmpeg::av_packet_alloc();
-
-        peripherals
-            .display
-            .set_render_mode(vexide::devices::display::RenderMode::Immediate);
-        println!("Ready to render");
-
-        /*
-        let scaled = ffmpeg::av_frame_alloc();
-        (*scaled).format = ffmpeg::AV_PIX_FMT_0RGB;
-        (*scaled).width = Display::HORIZONTAL_RESOLUTION as i32;
-        (*scaled).height = Display::VERTICAL_RESOLUTION as i32;
-        ffmpeg::av_frame_get_buffer(scaled, 0);
-
-        let scale_context = ffmpeg::sws_getContext(
-            (*codec_ctx).coded_width,
-            (*codec_ctx).coded_height,
-            (*(*stream).codecpar).format,
-            (*scaled).width,
-            (*scaled).height,
-            (*scaled).format,
-            ffmpeg::SWS_BILINEAR as i32,
-            core::ptr::null_mut(),
-            core::ptr::null_mut(),
-            core::ptr::null_mut(),
-        );
-        */
-
-        let mut scaled_frame = alloc::vec![rgb::Bgra::new_bgra(0u8, 0, 0, 0); Display::HORIZONTAL_RESOLUTION as usize * Display::VERTICAL_RESOLUTION as usize].into_boxed_slice();
-
-        let mut last_frame = Instant::now();
-        while ffmpeg::av_read_frame(av_context, packet) >= 0 {
-            let result = ffmpeg::avcodec_send_packet(codec_ctx, packet);
-            if result < 0 {
-                let mut str = [0u8; 1024];
-                ffmpeg::av_strerror(result, &mut str as *mut _, 1024);
-                println!(
-                    "Failed to decode packet: {}",
-                    CStr::from_bytes_until_nul(&str)
-                        .expect("shitface")
-                        .to_str()
-                        .expect("shitface")
-                );
-                return;
-            }
+/// Where `alloc_report` puts the full allocation report
+const ALLOC_REPORT_PATH: &str = "alloc_report.txt";
 
-            loop {
-                const AVERROR_EAGAIN: i32 = -(ffmpeg::EAGAIN as i32);
-                match ffmpeg::avcodec_receive_frame(codec_ctx, frame) {
-                    0.. => (),
-                    AVERROR_EOF | AVERROR_EAGAIN => {
-                        break;
-                    }
-                    result => {
-                        let mut str = [0u8; 1024];
-                        ffmpeg::av_strerror(result, &mut str as *mut _, 1024);
-                        println!(
-                            "Failed to decode packet: {}",
-                            CStr::from_bytes_until_nul(&str)
-                                .expect("shitface")
-                                .to_str()
-                                .expect("shitface")
-                        );
-                        return;
-                    }
-                }
+/// Log ffmpeg's heap usage once a file's been closed; anything still allocated by then is likely a
+/// leak
+fn report_allocations(config: &Config) {
+    println!("ffmpeg heap: {}", ffmpeg_alloc::stats());
+    if !config.alloc_report {
+        return;
+    }
 
-                //println!("Time to decode frame: {:?}", last_frame.elapsed());
-                // TODO: Colorspace conversion
+    let mut report = String::new();
+    // Writing to a String can't fail
+    let _ = ffmpeg_alloc::report(&mut report);
+    match vexide::fs::write(ALLOC_REPORT_PATH, report) {
+        Ok(()) => println!("Wrote {ALLOC_REPORT_PATH}"),
+        Err(err) => println!("Couldn't write {ALLOC_REPORT_PATH}: {err}"),
+    }
+}
 
-                let begin = Instant::now();
+fn set_log_level(level: LogLevel) {
+    let level = match level {
+        LogLevel::Quiet => ffmpeg::AV_LOG_QUIET as c_int,
+        LogLevel::Error => ffmpeg::AV_LOG_ERROR as c_int,
+        LogLevel::Warning => ffmpeg::AV_LOG_WARNING as c_int,
+        LogLevel::Info => ffmpeg::AV_LOG_INFO as c_int,
+        LogLevel::Verbose => ffmpeg::AV_LOG_VERBOSE as c_int,
+        LogLevel::Debug => ffmpeg::AV_LOG_DEBUG as c_int,
+    };
+    unsafe { ffmpeg::av_log_set_level(level) };
+}
+
+/// How playback of a file ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Finished,
+    Next,
+    Previous,
+    /// Back to the menu
+    Stopped,
+}
+
+/// How long an error playing a file stays up before moving on to the next one
+const ERROR_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Show `err` until the user presses something, or `timeout` runs out
+async fn report_error(
+    display: &mut Display,
+    controller: &Controller,
+    err: &PlayerError,
+    timeout: Option<Duration>,
+) {
+    println!("Error: {err}");
+    show_error(display, err);
+
+    let shown = Instant::now();
+    let timed_out = || timeout.is_some_and(|timeout| shown.elapsed() >= timeout);
+    let mut press_count = display.touch_status().press_count;
+    while held_buttons(controller) != Buttons::NONE && !timed_out() {
+        sleep(Controller::UPDATE_INTERVAL).await;
+    }
+    while held_buttons(controller) == Buttons::NONE
+        && poll_tap(display, &mut press_count).is_none()
+        && !timed_out()
+    {
+        sleep(Controller::UPDATE_INTERVAL).await;
+    }
+}
 
-                let line_size = (*frame).linesize;
-                let width = (*frame).width as usize;
-                let height = (*frame).height as usize;
+/// Where a new tap landed since `press_count` was last updated, relative to the area below the
+/// header (touch coordinates include it, drawing coordinates don't)
+fn poll_tap(display: &Display, press_count: &mut i32) -> Option<(i32, i32)> {
+    let event = display.touch_status();
+    if event.press_count == *press_count {
+        return None;
+    }
 
-                // Rescale image
-                // TODO: Bilinear/Average(area)
-                for y in 0..Display::VERTICAL_RESOLUTION as usize {
-                    let sy = y as f32 / f32::from(Display::VERTICAL_RESOLUTION);
-                    for x in 0..Display::HORIZONTAL_RESOLUTION as usize {
-                        let sx = x as f32 / f32::from(Display::HORIZONTAL_RESOLUTION);
+    *press_count = event.press_count;
+    Some((
+        event.point.x as i32,
+        event.point.y as i32 - Display::HEADER_HEIGHT as i32,
+    ))
+}
 
-                        let nx = (sx * width as f32) as usize;
-                        let ny = (sy * height as f32) as usize;
+/// Blit `pixels` (0RGB, stride `region.width`) to `region` on screen
+fn present(region: Region, pixels: &[u32]) {
+    unsafe {
+        // Corners are inclusive
+        let top = region.y as i32 + Display::HEADER_HEIGHT as i32;
+        vex_sdk::vexDisplayCopyRect(
+            region.x as i32,
+            top,
+            (region.x + region.width) as i32 - 1,
+            top + region.height as i32 - 1,
+            pixels.as_ptr().cast_mut(),
+            region.width as i32,
+        );
+    }
+}
 
-                        let dst =
-                            &mut scaled_frame[y * Display::HORIZONTAL_RESOLUTION as usize + x];
+/// Buttons currently held on `controller`; nothing if it's disconnected
+fn held_buttons(controller: &Controller) -> Buttons {
+    let Ok(state) = controller.state() else {
+        return Buttons::NONE;
+    };
 
-                        match (*frame).format {
-                            ffmpeg::AV_PIX_FMT_YUV420P => {
-                                // Copy over into 4:4:4 format
-                                let luma_stride = line_size[0] as usize;
-                                let luma_plane = (*frame).data[0].cast_const();
+    Buttons::NONE
+        .with(Buttons::A, state.button_a.is_pressed())
+        .with(Buttons::B, state.button_b.is_pressed())
+        .with(Buttons::X, state.button_x.is_pressed())
+        .with(Buttons::Y, state.button_y.is_pressed())
+        .with(Buttons::UP, state.button_up.is_pressed())
+        .with(Buttons::DOWN, state.button_down.is_pressed())
+        .with(Buttons::LEFT, state.button_left.is_pressed())
+        .with(Buttons::RIGHT, state.button_right.is_pressed())
+        .with(Buttons::L1, state.button_l1.is_pressed())
+        .with(Buttons::L2, state.button_l2.is_pressed())
+        .with(Buttons::R1, state.button_r1.is_pressed())
+        .with(Buttons::R2, state.button_r2.is_pressed())
+}
+
+fn show_error(display: &mut Display, err: &PlayerError) {
+    display.set_render_mode(vexide::devices::display::RenderMode::Immediate);
+    display.erase(Rgb::new(0, 0, 0));
+
+    let title = match err {
+        PlayerError::Config(_) => "Bad config",
+        _ => "Playback failed",
+    };
+    let font = Font::new(FontSize::MEDIUM, FontFamily::Proportional);
+    display.draw_text(
+        &Text::new(title, font, Point2 { x: 10, y: 10 }),
+        Rgb::new(255, 80, 80),
+        None::<Rgb<u8>>,
+    );
 
-                                let blue_stride = line_size[1] as usize;
-                                let blue_plane = (*frame).data[1].cast_const();
+    // Config errors run long; wrap them at spaces
+    const LINE_CHARS: usize = 44;
+    let message = err.to_string();
+    let mut line = String::new();
+    let mut y = 40;
+    for word in message.split(' ') {
+        if !line.is_empty() && line.len() + 1 + word.len() > LINE_CHARS {
+            display.draw_text(
+                &Text::new(&line, font, Point2 { x: 10, y }),
+                Rgb::new(255, 255, 255),
+                None::<Rgb<u8>>,
+            );
+            line.clear();
+            y += 24;
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(word);
+    }
+    display.draw_text(
+        &Text::new(&line, font, Point2 { x: 10, y }),
+        Rgb::new(255, 255, 255),
+        None::<Rgb<u8>>,
+    );
+}
 
-                                let red_stride = line_size[2] as usize;
-                                let red_plane = (*frame).data[2].cast_const();
+/// A-B points to mark on the progress bar
+fn loop_points(repeat: Repeat, loop_start: Option<Duration>) -> [Option<Duration>; 2] {
+    match repeat {
+        Repeat::Segment { start, end } => [Some(start), Some(end)],
+        _ => [loop_start, None],
+    }
+}
 
-                                let luma = luma_plane.add(ny * luma_stride + nx).read();
+async fn play(
+    display: &mut Display,
+    controller: &Controller,
+    path: &str,
+    config: &Config,
+) -> Result<Outcome, PlayerError> {
+    let player = player::Player::open(path, config)?;
+    let duration = player.duration();
+    let mut governor = quality::Governor::new(player.max_quality_level());
+
+    let buffers = pipeline::allocate(pipeline::buffer_count(config))?;
+    println!("Decoding up to {} frames ahead", buffers.len() - 1);
+    let shared = Rc::new(RefCell::new(pipeline::Shared::new(player, buffers)));
+    // Cancelled when this gets dropped, however playback ends
+    let _producer = spawn(pipeline::produce(shared.clone(), config.clone()));
+
+    display.set_render_mode(vexide::devices::display::RenderMode::Immediate);
+    println!("Ready to render");
+
+    let mut scheduler = scheduler::Scheduler::new(SystemClock(Instant::now()));
+    let mut controls = controls::Controls::default();
+    let mut overlay = overlay::Overlay::default();
+    let mut press_count = display.touch_status().press_count;
+    let mut paused = false;
+    // Loop point A, while waiting for B
+    let mut loop_start = None;
+    // Kept until the next frame replaces it, for redrawing the overlay while paused
+    let mut on_screen: Option<pipeline::Slot> = None;
+    // Show the next frame right away, even though paused
+    let mut stepping = false;
+
+    loop {
+        // Seeks are relative to the frame on screen
+        let shown = on_screen.as_ref().map(|slot| slot.position);
+        let position = shown.unwrap_or_default();
+
+        // Handle the controller & touchscreen; sits here while paused
+        let mut seek_target = None;
+        let was_paused = paused;
+        loop {
+            let now = scheduler.clock().now();
+            let region = on_screen.as_ref().map(|slot| slot.region);
+            let repeat = shared.borrow().player.repeat();
+            let status = overlay::Status {
+                position,
+                duration,
+                paused,
+                loop_points: loop_points(repeat, loop_start),
+            };
 
-                                // Each CbCr represents a 2x2 field of luma
-                                let chroma_blue =
-                                    blue_plane.add((ny >> 1) * blue_stride + (nx >> 1)).read();
-                                let chroma_red =
-                                    red_plane.add((ny >> 1) * red_stride + (nx >> 1)).read();
+            let mut redraw = false;
+            let mut tap_command = None;
+            if let (Some((x, y)), Some(region)) = (poll_tap(display, &mut press_count), region) {
+                redraw = true;
+                tap_command = overlay.touch(
+                    (x - region.x as i32, y - region.y as i32),
+                    (region.width, region.height),
+                    status,
+                    now,
+                );
+            }
 
-                                *dst = Bgra::new_bgra(luma, chroma_blue, chroma_red, 0);
-                            }
-                            format => unimplemented!("Unsupported pixel format: {format}"),
+            let held = held_buttons(controller);
+            for command in controls.update(held, now, paused).chain(tap_command) {
+                let mut shared = shared.borrow_mut();
+                let player = &mut shared.player;
+                match command {
+                    Command::TogglePause => {
+                        paused = !paused;
+                        redraw = true;
+                    }
+                    Command::SeekTo(target) => seek_target = Some(target),
+                    Command::SeekForward(by) => {
+                        seek_target = Some(seek_target.unwrap_or(position) + by)
+                    }
+                    Command::SeekBackward(by) => {
+                        seek_target = Some(seek_target.unwrap_or(position).saturating_sub(by))
+                    }
+                    Command::NextFile => return Ok(Outcome::Next),
+                    Command::PreviousFile => return Ok(Outcome::Previous),
+                    Command::Stop => return Ok(Outcome::Stopped),
+                    Command::Faster | Command::Slower | Command::NormalSpeed => {
+                        let rate = match command {
+                            Command::NormalSpeed => 100,
+                            _ => controls::step_speed(scheduler.rate(), command == Command::Faster),
+                        };
+                        scheduler.set_rate(rate);
+                        player.set_rate(rate);
+                    }
+                    Command::StepForward => stepping = true,
+                    Command::StepBackward => {
+                        if let Some(shown) = shown {
+                            // Accurate seeking lands on the frame before the one on screen
+                            seek_target = Some(shown.saturating_sub(player.frame_interval()));
+                            stepping = true;
                         }
                     }
-                }
-
-                //println!("Took {:?} to Rescale", begin.elapsed());
-                //let begin = Instant::now();
-
-                // Convert to 0RGB (8bit)
-                match (*frame).format {
-                    ffmpeg::AV_PIX_FMT_0RGB => (), // Do nothing; target fmt
-                    ffmpeg::AV_PIX_FMT_YUV420P => {
-                        // TODO: Fix color fringing
-
-                        // TODO: Change these constants depending on colorspace
-                        const CHROMA_BLUE_WEIGHTS: [i16; 2] = [
-                            24,  //-0.1873,
-                            238, // 1.8556,
-                        ];
-                        const CHROMA_RED_WEIGHTS: [i16; 2] = [
-                            202, // 1.5748,
-                            60,  // -0.4681,
-                        ];
-
-                        use core::arch::arm::*;
-
-                        let half = vmovq_n_s16(128);
-
-                        for block in (0..scaled_frame.len()).step_by(8) {
-                            let uint8x8x4_t(luma, chroma_red, chroma_blue, alpha) =
-                                vld4_u8(scaled_frame.as_ptr().add(block).cast());
-
-                            // Scale YUV
-                            let luma = vshlq_n_s16::<7>(vreinterpretq_s16_u16(vmovl_u8(luma)));
-                            let chroma_red =
-                                vsubq_s16(vreinterpretq_s16_u16(vmovl_u8(chroma_red)), half);
-                            let chroma_blue =
-                                vsubq_s16(vreinterpretq_s16_u16(vmovl_u8(chroma_blue)), half);
-
-                            let red = vaddq_s16(
-                                luma,
-                                vmulq_s16(chroma_red, vmovq_n_s16(CHROMA_RED_WEIGHTS[0])),
-                            );
-                            let green = vsubq_s16(
-                                luma,
-                                vaddq_s16(
-                                    vmulq_s16(chroma_blue, vmovq_n_s16(CHROMA_BLUE_WEIGHTS[0])),
-                                    vmulq_s16(chroma_red, vmovq_n_s16(CHROMA_RED_WEIGHTS[1])),
-                                ),
-                            );
-                            let blue = vaddq_s16(
-                                luma,
-                                vmulq_s16(chroma_blue, vmovq_n_s16(CHROMA_BLUE_WEIGHTS[1])),
-                            );
-
-                            let padding = vmovq_n_s16(64);
-                            vst4_s8(
-                                scaled_frame.as_mut_ptr().add(block).cast(),
-                                int8x8x4_t(
-                                    vmovn_s16(vshrq_n_s16::<7>(vaddq_s16(red, padding))),
-                                    vmovn_s16(vshrq_n_s16::<7>(vaddq_s16(green, padding))),
-                                    vmovn_s16(vshrq_n_s16::<7>(vaddq_s16(blue, padding))),
-                                    vreinterpret_s8_u8(alpha),
-                                ),
-                            );
+                    Command::MarkLoop => {
+                        if let Repeat::Segment { .. } = player.repeat() {
+                            player.set_repeat(match config.loop_mode {
+                                LoopMode::File => Repeat::File,
+                                _ => Repeat::Off,
+                            });
+                        } else if let Some(start) = loop_start.take()
+                            && position > start
+                        {
+                            player.set_repeat(Repeat::Segment {
+                                start,
+                                end: position,
+                            });
+                        } else {
+                            loop_start = Some(position);
                         }
+                        redraw = true;
                     }
-                    format => unimplemented!("Unsupported pixel format: {format}"),
                 }
+            }
 
-                //println!("Took {:?} to Resample to RGB", begin.elapsed());
-
-                /*
-                // Rescale to brain size + color format
-                ffmpeg::sws_scale(
-                    scale_context,
-                    &(*frame).data as *const *mut _ as *const *const _,
-                    &(*frame).linesize as *const _,
-                    0,
-                    (*frame).height,
-                    &(*scaled).data as *const *mut _,
-                    &(*scaled).linesize as *const _,
-                );
+            // The queue doesn't move while paused, so refresh the overlay on the frame on screen
+            if paused
+                && redraw
+                && let Some(slot) = &mut on_screen
+            {
+                let region = slot.region;
+                let pixels = &mut slot.pixels[..region.area()];
+                let status = overlay::Status {
+                    paused,
+                    loop_points: loop_points(shared.borrow().player.repeat(), loop_start),
+                    ..status
+                };
+                overlay.draw(pixels, (region.width, region.height), status, now);
+                present(region, pixels);
+            }
 
-                let raw_pixels = core::slice::from_raw_parts(
-                    (*scaled).data[0],
-                    (*scaled).linesize[0] as usize * (*scaled).height as usize,
-                );
-                */
-
-                //let fract = (*stream).time_base.num as f64 / (*stream).time_base.den as f64;
-                //let pres = (*packet).pts as f64 * fract;
-                //println!(
-                //    "current time: {}, presenting at: {pres}",
-                //    start.elapsed().as_secs_f64()
-                //);
-                //sleep_until(start + Duration::from_secs_f64(pres)).await;
-
-                vex_sdk::vexDisplayCopyRect(
-                    0,
-                    Display::HEADER_HEIGHT as i32,
-                    Display::HORIZONTAL_RESOLUTION as i32,
-                    Display::VERTICAL_RESOLUTION as i32 + Display::HEADER_HEIGHT as i32,
-                    bytemuck::cast_slice::<_, u32>(&scaled_frame)
-                        .as_ptr()
-                        .cast_mut(),
-                    Display::HORIZONTAL_RESOLUTION as i32,
-                );
+            if !paused || seek_target.is_some() || stepping {
+                break;
+            }
+            sleep(Controller::UPDATE_INTERVAL).await;
+        }
+        if was_paused && !paused {
+            scheduler.reset();
+        }
+
+        if let Some(target) = seek_target {
+            println!("Seeking to {target:?}");
+            shared.borrow_mut().seek_to(target)?;
+            scheduler.reset();
+            continue;
+        }
 
-                //peripherals.display.draw_buffer(region, buf, src_stride);
-                ffmpeg::av_frame_unref(frame);
-                last_frame = Instant::now();
+        let (next, finished) = {
+            let mut shared = shared.borrow_mut();
+            if let Some(err) = shared.error.take() {
+                return Err(err);
             }
+            (shared.queue.pop(), shared.finished)
+        };
+        let Some(mut slot) = next else {
+            if finished {
+                break;
+            }
+            // Decoder's fallen behind (or just seeked); let it catch up
+            sleep(pipeline::IDLE_POLL).await;
+            continue;
+        };
 
-            ffmpeg::av_packet_unref(packet);
+        // Frame steps go up straight away, whatever the clock says
+        let decision = if stepping {
+            None
+        } else {
+            slot.pts.map(|pts| scheduler.schedule(pts))
+        };
+        if let Some(decision) = decision {
+            let late_by = match decision {
+                scheduler::Decision::Present { deadline } => {
+                    scheduler.clock().now().saturating_sub(deadline)
+                }
+                scheduler::Decision::Drop { late_by } => late_by,
+            };
+            if let Some(level) = governor.update(late_by) {
+                println!("Decode quality now {level:?}");
+                shared.borrow_mut().player.set_quality(level);
+            }
         }
+        let deadline = match decision {
+            Some(scheduler::Decision::Present { deadline }) => Some(deadline),
+            Some(scheduler::Decision::Drop { .. }) => {
+                shared.borrow_mut().queue.recycle(slot);
+                continue;
+            }
+            None => None, // No timestamp; show asap
+        };
 
-        //ffmpeg::sws_freeContext(scale_context);
-        ffmpeg::avformat_close_input(&mut av_context as *mut _);
+        let region = slot.region;
+        let pixels = &mut slot.pixels[..region.area()];
+        overlay.draw(
+            pixels,
+            (region.width, region.height),
+            overlay::Status {
+                position: slot.position,
+                duration,
+                paused,
+                loop_points: loop_points(shared.borrow().player.repeat(), loop_start),
+            },
+            scheduler.clock().now(),
+        );
+
+        if let Some(deadline) = deadline {
+            sleep(scheduler.time_until(deadline)).await;
+        }
 
-        let _: Box<File> = Box::from_raw((*avio_ctx).opaque.cast());
-        ffmpeg::av_freep(avio_buffer.cast());
-        ffmpeg::avio_context_free(&mut avio_ctx as *mut _);
+        // Clear out the bars when the video changes size; otherwise only its region gets redrawn
+        if on_screen.as_ref().map(|slot| slot.region) != Some(region) {
+            display.erase(Rgb::new(0, 0, 0));
+        }
+        present(region, pixels);
+        stepping = false;
 
-        ffmpeg::avformat_free_context(av_context);
+        if let Some(previous) = on_screen.replace(slot) {
+            shared.borrow_mut().queue.recycle(previous);
+        }
     }
+
+    let stats = scheduler.stats();
+    println!(
+        "Presented {} frames, dropped {}",
+        stats.presented, stats.dropped
+    );
+    let queue = shared.borrow().queue.stats();
+    println!(
+        "Queue of {}: {}.{:02} frames waiting on average, peak {}, {} underruns, {} overruns",
+        queue.capacity,
+        queue.average_occupancy() / 100,
+        queue.average_occupancy() % 100,
+        queue.peak,
+        queue.underruns,
+        queue.overruns
+    );
+    let quality = governor.stats();
+    println!(
+        "Decode quality {:?} (worst {:?}), lowered {} times, raised {} times",
+        quality.level, quality.worst, quality.degraded, quality.recovered
+    );
+
+    Ok(Outcome::Finished)
 }
@@ -0,0 +1,707 @@
+//! Owning wrappers around the ffmpeg demux/decode types
+
+use alloc::{boxed::Box, string::String, vec::Vec};
+use core::{
+    ffi::{CStr, c_int, c_void},
+    time::Duration,
+};
+
+use vexide::{
+    fs::File,
+    io::{Read, Seek, println},
+};
+
+use crate::{
+    color::{Matrix, Range},
+    config::Track,
+    error::{
+        AVERROR_DECODER_NOT_FOUND, AVERROR_EAGAIN, AVERROR_EINVAL, AVERROR_EIO, AVERROR_EOF,
+        PlayerError, av_check,
+    },
+    ffmpeg,
+    keyframes::Keyframe,
+    pixfmt::{ChromaLocation, FormatDesc, PlaneLayout},
+    scheduler::timestamp_to_duration,
+};
+
+/// Opaque handed to the AVIO callbacks. IO errors are stashed here, since ffmpeg only gets an `AVERROR`
+struct FileSource {
+    file: File,
+    error: Option<vexide::io::Error>,
+}
+
+#[unsafe(no_mangle)]
+unsafe extern "C" fn vexide_file_read(context: *mut c_void, ptr: *mut u8, size: i32) -> i32 {
+    let source = unsafe { &mut *context.cast::<FileSource>() };
+
+    // Read from current position into
+    let buf = unsafe { &mut *core::ptr::slice_from_raw_parts_mut(ptr, size as usize) };
+    match source.file.read(buf) {
+        Ok(0) if size != 0 => AVERROR_EOF,
+        Ok(read) => read as i32,
+        Err(err) => {
+            source.error = Some(err);
+            AVERROR_EIO
+        }
+    }
+}
+
+unsafe extern "C" fn vexide_file_seek(context: *mut c_void, offset: i64, whence: c_int) -> i64 {
+    let source = unsafe { &mut *context.cast::<FileSource>() };
+
+    const SEEK_SET: c_int = 0;
+    const SEEK_CUR: c_int = 1;
+    const SEEK_END: c_int = 2;
+    const SEEK_SIZE: c_int = ffmpeg::AVSEEK_SIZE as c_int;
+
+    let offset = match whence & !(ffmpeg::AVSEEK_FORCE as c_int) {
+        SEEK_SET => vexide::io::SeekFrom::Start(offset as u64),
+        SEEK_CUR => vexide::io::SeekFrom::Current(offset),
+        SEEK_END => vexide::io::SeekFrom::End(offset),
+        SEEK_SIZE => {
+            return match source.file.metadata().map(|metadata| metadata.len()) {
+                Ok(Some(size)) => size as i64,
+                Ok(None) => AVERROR_EINVAL as i64,
+                Err(err) => {
+                    source.error = Some(err);
+                    AVERROR_EIO as i64
+                }
+            };
+        }
+        _ => return AVERROR_EINVAL as i64,
+    };
+
+    match source.file.seek(offset) {
+        Ok(new_offset) => new_offset as i64,
+        Err(err) => {
+            source.error = Some(err);
+            AVERROR_EIO as i64
+        }
+    }
+}
+
+/// AVIO context reading from a vexide [`File`]
+struct FileIo(*mut ffmpeg::AVIOContext);
+
+impl FileIo {
+    fn new(file: File, buffer_size: usize) -> Result<Self, PlayerError> {
+        unsafe {
+            let buffer = ffmpeg::av_malloc(buffer_size);
+            if buffer.is_null() {
+                return Err(PlayerError::Alloc);
+            }
+
+            let opaque = Box::into_raw(Box::new(FileSource { file, error: None }));
+            let ctx = ffmpeg::avio_alloc_context(
+                buffer.cast(),
+                buffer_size as c_int,
+                0,
+                opaque.cast(),
+                Some(vexide_file_read),
+                None, // Dont think this needs to be set
+                Some(vexide_file_seek),
+            );
+            if ctx.is_null() {
+                drop(Box::from_raw(opaque));
+                ffmpeg::av_free(buffer);
+                return Err(PlayerError::Alloc);
+            }
+
+            Ok(Self(ctx))
+        }
+    }
+
+    /// Error for a failed ffmpeg call, preferring the underlying IO error if that's what caused it
+    fn error(&self, code: c_int) -> PlayerError {
+        let source = unsafe { &mut *(*self.0).opaque.cast::<FileSource>() };
+        match source.error.take() {
+            Some(err) => PlayerError::Filesystem(err),
+            None => PlayerError::from_av(code),
+        }
+    }
+}
+
+impl Drop for FileIo {
+    fn drop(&mut self) {
+        unsafe {
+            drop(Box::<FileSource>::from_raw((*self.0).opaque.cast()));
+            // The buffer may have been swapped out by avio, so free whatever it currently holds
+            ffmpeg::av_freep((&raw mut (*self.0).buffer).cast());
+            ffmpeg::avio_context_free(&mut self.0);
+        }
+    }
+}
+
+/// Decoder implementation picked by ffmpeg for a stream
+#[derive(Clone, Copy)]
+pub struct Codec(*const ffmpeg::AVCodec);
+
+impl Codec {
+    pub fn id(&self) -> ffmpeg::AVCodecID {
+        unsafe { (*self.0).id }
+    }
+
+    pub fn name(&self) -> &str {
+        unsafe { CStr::from_ptr((*self.0).name).to_str().unwrap_or("unknown") }
+    }
+
+    /// 0 if it can't decode at reduced size
+    pub fn max_lowres(&self) -> u8 {
+        unsafe { (*self.0).max_lowres }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct InputOptions {
+    /// Size of the AVIO read buffer
+    pub buffer_size: usize,
+    /// Turn on every `AVFormatContext` debug flag
+    pub debug: bool,
+    /// How much avformat may read while probing streams; its own default if `None`
+    pub probe_size: Option<i64>,
+}
+
+/// Opened & probed demuxer
+pub struct FormatInput {
+    ctx: *mut ffmpeg::AVFormatContext,
+    // Must outlive `ctx`, so keep it declared after
+    io: FileIo,
+}
+
+impl FormatInput {
+    pub fn open(file: File, options: InputOptions) -> Result<Self, PlayerError> {
+        let io = FileIo::new(file, options.buffer_size)?;
+
+        unsafe {
+            let mut ctx = ffmpeg::avformat_alloc_context();
+            if ctx.is_null() {
+                return Err(PlayerError::Alloc);
+            }
+            if options.debug {
+                (*ctx).debug = !0;
+            }
+            if let Some(probe_size) = options.probe_size {
+                (*ctx).probesize = probe_size;
+            }
+            (*ctx).pb = io.0;
+
+            // Frees `ctx` on failure
+            let result = ffmpeg::avformat_open_input(
+                &mut ctx,
+                core::ptr::null_mut(),
+                core::ptr::null_mut(),
+                core::ptr::null_mut(),
+            );
+            if result < 0 {
+                return Err(io.error(result));
+            }
+            let input = Self { ctx, io };
+            println!("AVFormat Open Input");
+
+            let result = ffmpeg::avformat_find_stream_info(input.ctx, core::ptr::null_mut());
+            if result < 0 {
+                return Err(input.io.error(result));
+            }
+            println!("AVFormat Find Stream Info");
+
+            Ok(input)
+        }
+    }
+
+    /// Length of the whole file, if the container says
+    pub fn duration(&self) -> Option<Duration> {
+        let duration = unsafe { (*self.ctx).duration };
+        timestamp_to_duration(duration, 1, ffmpeg::AV_TIME_BASE as i32)
+    }
+
+    pub fn streams(&self) -> &[*mut ffmpeg::AVStream] {
+        unsafe {
+            if (*self.ctx).streams.is_null() {
+                return &[];
+            }
+            core::slice::from_raw_parts((*self.ctx).streams, (*self.ctx).nb_streams as usize)
+        }
+    }
+
+    pub fn stream(&self, index: usize) -> Option<&ffmpeg::AVStream> {
+        self.streams().get(index).map(|&stream| unsafe { &*stream })
+    }
+
+    /// Best video stream & the decoder to go with it
+    pub fn best_video_stream(&self) -> Result<(usize, Codec), PlayerError> {
+        let mut codec: *const ffmpeg::AVCodec = core::ptr::null();
+        let index = av_check(unsafe {
+            ffmpeg::av_find_best_stream(self.ctx, ffmpeg::AVMEDIA_TYPE_VIDEO, -1, -1, &mut codec, 0)
+        })?;
+
+        Ok((index as usize, Codec(codec)))
+    }
+
+    /// Indices of the actual video streams, leaving out cover art & other attached pictures
+    pub fn video_streams(&self) -> Vec<usize> {
+        self.streams()
+            .iter()
+            .enumerate()
+            .filter(|&(_, &stream)| unsafe {
+                (*(*stream).codecpar).codec_type == ffmpeg::AVMEDIA_TYPE_VIDEO
+                    && (*stream).disposition & ffmpeg::AV_DISPOSITION_ATTACHED_PIC as c_int == 0
+            })
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    /// The video stream `track` picks & the decoder to go with it
+    pub fn video_stream(&self, track: &Track) -> Result<(usize, Codec), PlayerError> {
+        let video_streams = self.video_streams();
+        let index = match track {
+            Track::Best => return self.best_video_stream(),
+            Track::Index(index) => video_streams.get(*index).copied(),
+            Track::Title(title) => video_streams.into_iter().find(|&index| {
+                self.stream_title(index)
+                    .is_some_and(|candidate| candidate.eq_ignore_ascii_case(title))
+            }),
+        }
+        .ok_or_else(|| PlayerError::NoSuchTrack(track.clone()))?;
+
+        let codec = unsafe {
+            let stream = self.streams()[index];
+            ffmpeg::avcodec_find_decoder((*(*stream).codecpar).codec_id)
+        };
+        if codec.is_null() {
+            return Err(PlayerError::from_av(AVERROR_DECODER_NOT_FOUND));
+        }
+        Ok((index, Codec(codec)))
+    }
+
+    /// The stream's `title` tag
+    pub fn stream_title(&self, index: usize) -> Option<&str> {
+        let stream = *self.streams().get(index)?;
+        unsafe {
+            let entry =
+                ffmpeg::av_dict_get((*stream).metadata, c"title".as_ptr(), core::ptr::null(), 0);
+            if entry.is_null() {
+                return None;
+            }
+            CStr::from_ptr((*entry).value).to_str().ok()
+        }
+    }
+
+    /// Have the demuxer skip every stream but `index`, since nothing else gets decoded
+    pub fn discard_all_but(&mut self, index: usize) {
+        for (other, &stream) in self.streams().iter().enumerate() {
+            if other != index {
+                unsafe { (*stream).discard = ffmpeg::AVDISCARD_ALL };
+            }
+        }
+    }
+
+    /// Read the next packet. Returns `false` at end of file
+    pub fn read_packet(&mut self, packet: &mut Packet) -> Result<bool, PlayerError> {
+        packet.unref();
+        match unsafe { ffmpeg::av_read_frame(self.ctx, packet.0) } {
+            0.. => Ok(true),
+            AVERROR_EOF => Ok(false),
+            result => Err(self.io.error(result)),
+        }
+    }
+
+    /// Jump to the keyframe at or before `timestamp` (in the stream's time base)
+    pub fn seek(&mut self, stream_index: usize, timestamp: i64) -> Result<(), PlayerError> {
+        let result = unsafe {
+            ffmpeg::av_seek_frame(
+                self.ctx,
+                stream_index as c_int,
+                timestamp,
+                ffmpeg::AVSEEK_FLAG_BACKWARD as c_int,
+            )
+        };
+        if result < 0 {
+            return Err(self.io.error(result));
+        }
+        Ok(())
+    }
+
+    /// Keyframes in the container's own index (MP4 sample tables, Matroska cues, ...)
+    pub fn index_entries(&self, stream_index: usize) -> Vec<Keyframe> {
+        let Some(&stream) = self.streams().get(stream_index) else {
+            return Vec::new();
+        };
+
+        unsafe {
+            (0..ffmpeg::avformat_index_get_entries_count(stream))
+                .map(|index| ffmpeg::avformat_index_get_entry(stream, index))
+                .filter(|entry| {
+                    !entry.is_null()
+                        && (**entry).flags() as c_int & ffmpeg::AVINDEX_KEYFRAME as c_int != 0
+                })
+                .map(|entry| Keyframe {
+                    timestamp: (*entry).timestamp,
+                    position: (*entry).pos,
+                })
+                .collect()
+        }
+    }
+
+    /// Hand `keyframes` to the demuxer, so seeking can use them
+    pub fn add_index_entries(&mut self, stream_index: usize, keyframes: &[Keyframe]) {
+        let Some(&stream) = self.streams().get(stream_index) else {
+            return;
+        };
+
+        for keyframe in keyframes {
+            unsafe {
+                ffmpeg::av_add_index_entry(
+                    stream,
+                    keyframe.position,
+                    keyframe.timestamp,
+                    0,
+                    0,
+                    ffmpeg::AVINDEX_KEYFRAME as c_int,
+                );
+            }
+        }
+    }
+
+    pub fn as_ptr(&self) -> *const ffmpeg::AVFormatContext {
+        self.ctx
+    }
+}
+
+impl Drop for FormatInput {
+    fn drop(&mut self) {
+        // Custom IO is left alone by ffmpeg, `FileIo` cleans that up
+        unsafe { ffmpeg::avformat_close_input(&mut self.ctx) };
+    }
+}
+
+/// What [`Decoder::receive_frame`] got
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Receive {
+    Frame,
+    /// Send more packets first
+    NeedsInput,
+    /// Every frame is out after [`Decoder::send_eof`]
+    Drained,
+}
+
+pub struct Decoder(*mut ffmpeg::AVCodecContext);
+
+impl Decoder {
+    /// Decodes at 1/2^`lowres` size, clamped to what `codec` supports. Decoders only look at that
+    /// when opened, so changing it means opening a new one
+    pub fn open(codec: Codec, stream: &ffmpeg::AVStream, lowres: u8) -> Result<Self, PlayerError> {
+        unsafe {
+            let ctx = ffmpeg::avcodec_alloc_context3(codec.0);
+            if ctx.is_null() {
+                return Err(PlayerError::Alloc);
+            }
+            let decoder = Self(ctx);
+
+            av_check(ffmpeg::avcodec_parameters_to_context(
+                decoder.0,
+                stream.codecpar,
+            ))?;
+            (*decoder.0).lowres = lowres.min(codec.max_lowres()) as c_int;
+            av_check(ffmpeg::avcodec_open2(
+                decoder.0,
+                codec.0,
+                core::ptr::null_mut(),
+            ))?;
+
+            Ok(decoder)
+        }
+    }
+
+    pub fn send_packet(&mut self, packet: &Packet) -> Result<(), PlayerError> {
+        av_check(unsafe { ffmpeg::avcodec_send_packet(self.0, packet.0) })?;
+        Ok(())
+    }
+
+    /// Signal end of stream, so the decoder hands out whatever frames it's still holding on to
+    pub fn send_eof(&mut self) -> Result<(), PlayerError> {
+        match unsafe { ffmpeg::avcodec_send_packet(self.0, core::ptr::null()) } {
+            // Already draining
+            AVERROR_EOF => Ok(()),
+            result => av_check(result).map(drop),
+        }
+    }
+
+    /// Pull a decoded frame into `frame`
+    pub fn receive_frame(&mut self, frame: &mut Frame) -> Result<Receive, PlayerError> {
+        match unsafe { ffmpeg::avcodec_receive_frame(self.0, frame.as_mut_ptr()) } {
+            0.. => Ok(Receive::Frame),
+            AVERROR_EAGAIN => Ok(Receive::NeedsInput),
+            AVERROR_EOF => Ok(Receive::Drained),
+            result => Err(PlayerError::from_av(result)),
+        }
+    }
+
+    /// Which frames the decoder may skip decoding altogether
+    pub fn set_skip_frame(&mut self, discard: ffmpeg::AVDiscard) {
+        unsafe { (*self.0).skip_frame = discard };
+    }
+
+    /// Which frames get decoded without deblocking
+    pub fn set_skip_loop_filter(&mut self, discard: ffmpeg::AVDiscard) {
+        unsafe { (*self.0).skip_loop_filter = discard };
+    }
+
+    /// Which frames get decoded without the inverse transform, i.e. mostly as prediction
+    pub fn set_skip_idct(&mut self, discard: ffmpeg::AVDiscard) {
+        unsafe { (*self.0).skip_idct = discard };
+    }
+
+    /// Drop everything buffered inside the decoder, e.g. after seeking. Also takes it out of
+    /// draining mode
+    pub fn flush(&mut self) {
+        unsafe { ffmpeg::avcodec_flush_buffers(self.0) };
+    }
+
+    pub fn width(&self) -> u32 {
+        unsafe { (*self.0).width as u32 }
+    }
+
+    pub fn height(&self) -> u32 {
+        unsafe { (*self.0).height as u32 }
+    }
+
+    /// Power of two frames come out divided by
+    pub fn lowres(&self) -> u8 {
+        unsafe { (*self.0).lowres as u8 }
+    }
+
+    pub fn pix_fmt(&self) -> ffmpeg::AVPixelFormat {
+        unsafe { (*self.0).pix_fmt }
+    }
+
+    pub fn as_ptr(&self) -> *const ffmpeg::AVCodecContext {
+        self.0
+    }
+}
+
+impl Drop for Decoder {
+    fn drop(&mut self) {
+        unsafe { ffmpeg::avcodec_free_context(&mut self.0) };
+    }
+}
+
+/// File extensions (lowercase, no dot) of every demuxer compiled into this build
+pub fn demuxer_extensions() -> Vec<String> {
+    let mut extensions = Vec::new();
+    let mut opaque = core::ptr::null_mut();
+    loop {
+        let demuxer = unsafe { ffmpeg::av_demuxer_iterate(&mut opaque) };
+        if demuxer.is_null() {
+            break;
+        }
+
+        let list = unsafe { (*demuxer).extensions };
+        if list.is_null() {
+            continue;
+        }
+        let list = unsafe { CStr::from_ptr(list) }.to_string_lossy();
+        extensions.extend(list.split(',').map(|ext| ext.trim().to_ascii_lowercase()));
+    }
+
+    extensions.sort_unstable();
+    extensions.dedup();
+    extensions
+}
+
+/// Layout of the pixel formats we know how to draw
+pub fn describe(format: ffmpeg::AVPixelFormat) -> Option<FormatDesc> {
+    Some(match format {
+        ffmpeg::AV_PIX_FMT_YUV420P => FormatDesc::planar(1, 1, 8),
+        ffmpeg::AV_PIX_FMT_YUV422P => FormatDesc::planar(1, 0, 8),
+        ffmpeg::AV_PIX_FMT_YUV444P => FormatDesc::planar(0, 0, 8),
+        ffmpeg::AV_PIX_FMT_YUVJ420P => FormatDesc::planar(1, 1, 8).full_range(),
+        ffmpeg::AV_PIX_FMT_YUVJ422P => FormatDesc::planar(1, 0, 8).full_range(),
+        ffmpeg::AV_PIX_FMT_YUVJ444P => FormatDesc::planar(0, 0, 8).full_range(),
+        ffmpeg::AV_PIX_FMT_YUV420P10LE => FormatDesc::planar(1, 1, 10),
+        ffmpeg::AV_PIX_FMT_YUV422P10LE => FormatDesc::planar(1, 0, 10),
+        ffmpeg::AV_PIX_FMT_YUV444P10LE => FormatDesc::planar(0, 0, 10),
+        ffmpeg::AV_PIX_FMT_NV12 | ffmpeg::AV_PIX_FMT_NV21 => FormatDesc {
+            layout: PlaneLayout::SemiPlanar {
+                cb_first: format == ffmpeg::AV_PIX_FMT_NV12,
+            },
+            ..FormatDesc::planar(1, 1, 8)
+        },
+        ffmpeg::AV_PIX_FMT_GRAY8 => FormatDesc {
+            layout: PlaneLayout::Gray,
+            ..FormatDesc::planar(0, 0, 8)
+        },
+        _ => return None,
+    })
+}
+
+/// One plane of picture data, `stride * height` bytes long
+#[derive(Clone, Copy)]
+pub struct Plane<'a> {
+    pub data: &'a [u8],
+    pub stride: usize,
+    pub height: usize,
+}
+
+impl<'a> Plane<'a> {
+    pub fn row(&self, y: usize) -> &'a [u8] {
+        &self.data[y * self.stride..(y + 1) * self.stride]
+    }
+}
+
+pub struct Frame(*mut ffmpeg::AVFrame);
+
+impl Frame {
+    pub fn new() -> Result<Self, PlayerError> {
+        let frame = unsafe { ffmpeg::av_frame_alloc() };
+        if frame.is_null() {
+            Err(PlayerError::Alloc)
+        } else {
+            Ok(Self(frame))
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        unsafe { (*self.0).width as usize }
+    }
+
+    pub fn height(&self) -> usize {
+        unsafe { (*self.0).height as usize }
+    }
+
+    pub fn format(&self) -> ffmpeg::AVPixelFormat {
+        unsafe { (*self.0).format }
+    }
+
+    pub fn best_effort_timestamp(&self) -> i64 {
+        unsafe { (*self.0).best_effort_timestamp }
+    }
+
+    /// Y'CbCr matrix the frame is tagged with, if any
+    pub fn color_matrix(&self) -> Option<Matrix> {
+        match unsafe { (*self.0).colorspace } {
+            ffmpeg::AVCOL_SPC_BT709 => Some(Matrix::Bt709),
+            ffmpeg::AVCOL_SPC_BT470BG | ffmpeg::AVCOL_SPC_SMPTE170M => Some(Matrix::Bt601),
+            ffmpeg::AVCOL_SPC_BT2020_NCL | ffmpeg::AVCOL_SPC_BT2020_CL => Some(Matrix::Bt2020),
+            _ => None,
+        }
+    }
+
+    pub fn color_range(&self) -> Option<Range> {
+        match unsafe { (*self.0).color_range } {
+            ffmpeg::AVCOL_RANGE_MPEG => Some(Range::Limited),
+            ffmpeg::AVCOL_RANGE_JPEG => Some(Range::Full),
+            _ => None,
+        }
+    }
+
+    /// Untagged video is assumed to be left-sited, like MPEG-2 & H.264
+    pub fn chroma_location(&self) -> ChromaLocation {
+        match unsafe { (*self.0).chroma_location } {
+            ffmpeg::AVCHROMA_LOC_CENTER => ChromaLocation::Center,
+            ffmpeg::AVCHROMA_LOC_TOPLEFT => ChromaLocation::TopLeft,
+            ffmpeg::AVCHROMA_LOC_TOP => ChromaLocation::Top,
+            ffmpeg::AVCHROMA_LOC_BOTTOMLEFT => ChromaLocation::BottomLeft,
+            ffmpeg::AVCHROMA_LOC_BOTTOM => ChromaLocation::Bottom,
+            _ => ChromaLocation::Left,
+        }
+    }
+
+    /// Picture plane `index`, or `None` if the format doesn't have it (or it's stored bottom-up)
+    pub fn plane(&self, index: usize) -> Option<Plane<'_>> {
+        unsafe {
+            let frame = &*self.0;
+            let data = *frame.data.get(index)?;
+            let stride = usize::try_from(frame.linesize[index]).ok()?;
+            if data.is_null() {
+                return None;
+            }
+
+            let desc = ffmpeg::av_pix_fmt_desc_get(frame.format);
+            if desc.is_null() {
+                return None;
+            }
+
+            // Same rounding as `av_image_fill_pointers`
+            let shift = if index == 1 || index == 2 {
+                (*desc).log2_chroma_h
+            } else {
+                0
+            };
+            let height = (frame.height as usize + (1 << shift) - 1) >> shift;
+
+            Some(Plane {
+                data: core::slice::from_raw_parts(data, stride * height),
+                stride,
+                height,
+            })
+        }
+    }
+
+    pub fn unref(&mut self) {
+        unsafe { ffmpeg::av_frame_unref(self.0) };
+    }
+
+    pub fn as_ptr(&self) -> *const ffmpeg::AVFrame {
+        self.0
+    }
+
+    pub fn as_mut_ptr(&mut self) -> *mut ffmpeg::AVFrame {
+        self.0
+    }
+}
+
+impl Drop for Frame {
+    fn drop(&mut self) {
+        unsafe { ffmpeg::av_frame_free(&mut self.0) };
+    }
+}
+
+pub struct Packet(*mut ffmpeg::AVPacket);
+
+impl Packet {
+    pub fn new() -> Result<Self, PlayerError> {
+        let packet = unsafe { ffmpeg::av_packet_alloc() };
+        if packet.is_null() {
+            Err(PlayerError::Alloc)
+        } else {
+            Ok(Self(packet))
+        }
+    }
+
+    pub fn stream_index(&self) -> usize {
+        unsafe { (*self.0).stream_index as usize }
+    }
+
+    /// Presentation timestamp, falling back to the decode timestamp
+    pub fn timestamp(&self) -> i64 {
+        unsafe {
+            match (*self.0).pts {
+                i64::MIN => (*self.0).dts,
+                pts => pts,
+            }
+        }
+    }
+
+    /// Byte offset in the file, `-1` if unknown
+    pub fn position(&self) -> i64 {
+        unsafe { (*self.0).pos }
+    }
+
+    pub fn is_keyframe(&self) -> bool {
+        unsafe { (*self.0).flags & ffmpeg::AV_PKT_FLAG_KEY as c_int != 0 }
+    }
+
+    pub fn unref(&mut self) {
+        unsafe { ffmpeg::av_packet_unref(self.0) };
+    }
+
+    pub fn as_ptr(&self) -> *const ffmpeg::AVPacket {
+        self.0
+    }
+}
+
+impl Drop for Packet {
+    fn drop(&mut self) {
+        unsafe { ffmpeg::av_packet_free(&mut self.0) };
+    }
+}
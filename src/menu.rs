@@ -0,0 +1,311 @@
+//! Startup menu: pick a video, an `.m3u` playlist or a whole directory off the SD card
+
+use alloc::{format, string::String, vec, vec::Vec};
+
+use vexide::{
+    devices::{
+        controller::Controller,
+        display::{Display, Font, FontFamily, FontSize, Rect, RenderMode, Text},
+        math::Point2,
+        rgb::Rgb,
+    },
+    fs,
+    prelude::sleep,
+};
+
+use crate::{
+    config::{Config, LoopMode},
+    controls::Buttons,
+    error::PlayerError,
+    held_buttons, media,
+    playlist::{self, Playlist},
+    poll_tap,
+};
+
+const ROW_HEIGHT: i16 = 24;
+const LIST_TOP: i16 = 28;
+const FOOTER_TOP: i16 = Display::VERTICAL_RESOLUTION - 24;
+const VISIBLE_ROWS: usize = ((FOOTER_TOP - LIST_TOP) / ROW_HEIGHT) as usize;
+
+const BACKGROUND: Rgb<u8> = Rgb::new(0, 0, 0);
+const HIGHLIGHT: Rgb<u8> = Rgb::new(40, 60, 120);
+const FOREGROUND: Rgb<u8> = Rgb::new(255, 255, 255);
+const DIM: Rgb<u8> = Rgb::new(150, 150, 150);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Item {
+    /// Every video in the directory
+    PlayAll,
+    Playlist(String),
+    File(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Input {
+    Up,
+    Down,
+    Select,
+    ToggleLoop,
+    ToggleShuffle,
+    /// Touch, below the header
+    Tap(i32, i32),
+}
+
+/// Menu state; drawing & input polling live in [`choose`]
+pub struct Menu {
+    dir: String,
+    items: Vec<Item>,
+    /// Every video in `dir`, for [`Item::PlayAll`]
+    files: Vec<String>,
+    selected: usize,
+    scroll: usize,
+    looping: bool,
+    shuffle: bool,
+}
+
+impl Menu {
+    /// `names` are the files in `dir`; anything without one of `extensions` (or `.m3u`) is left out
+    pub fn new(dir: &str, mut names: Vec<String>, extensions: &[String]) -> Self {
+        names.sort_unstable_by_key(|name| name.to_ascii_lowercase());
+
+        let files: Vec<String> = names
+            .iter()
+            .filter(|name| playlist::has_extension(name, extensions))
+            .cloned()
+            .collect();
+
+        let mut items = Vec::new();
+        if files.len() > 1 {
+            items.push(Item::PlayAll);
+        }
+        items.extend(
+            names
+                .iter()
+                .filter(|name| playlist::is_playlist(name))
+                .map(|name| Item::Playlist(name.clone())),
+        );
+        items.extend(files.iter().map(|name| Item::File(name.clone())));
+
+        Self {
+            dir: dir.into(),
+            items,
+            files,
+            selected: 0,
+            scroll: 0,
+            looping: false,
+            shuffle: false,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Returns the chosen item, if `input` picked one
+    pub fn handle(&mut self, input: Input) -> Option<Item> {
+        match input {
+            Input::Up => self.select(self.selected.saturating_sub(1)),
+            Input::Down => self.select(self.selected + 1),
+            Input::Select => return self.items.get(self.selected).cloned(),
+            Input::ToggleLoop => self.looping = !self.looping,
+            Input::ToggleShuffle => self.shuffle = !self.shuffle,
+            Input::Tap(x, y) => {
+                let y = y as i16;
+                if y >= FOOTER_TOP {
+                    if x < Display::HORIZONTAL_RESOLUTION as i32 / 2 {
+                        self.looping = !self.looping;
+                    } else {
+                        self.shuffle = !self.shuffle;
+                    }
+                } else if y >= LIST_TOP {
+                    let index = self.scroll + ((y - LIST_TOP) / ROW_HEIGHT) as usize;
+                    if index < self.items.len() {
+                        self.select(index);
+                        return Some(self.items[index].clone());
+                    }
+                }
+            }
+        }
+
+        None
+    }
+
+    fn select(&mut self, index: usize) {
+        self.selected = index.min(self.items.len().saturating_sub(1));
+        if self.selected < self.scroll {
+            self.scroll = self.selected;
+        } else if self.selected >= self.scroll + VISIBLE_ROWS {
+            self.scroll = self.selected + 1 - VISIBLE_ROWS;
+        }
+    }
+
+    /// Files to play for `item`, with the loop/shuffle options applied
+    pub fn playlist(&self, item: &Item) -> Result<Playlist, PlayerError> {
+        match item {
+            Item::PlayAll => build(
+                self.files
+                    .iter()
+                    .map(|name| playlist::join(&self.dir, name))
+                    .collect(),
+                self.looping,
+                self.shuffle,
+            ),
+            Item::File(name) | Item::Playlist(name) => {
+                load_playlist(&playlist::join(&self.dir, name), self.looping, self.shuffle)
+            }
+        }
+    }
+
+    fn label(&self, item: &Item) -> String {
+        match item {
+            Item::PlayAll => format!("Play all ({} videos)", self.files.len()),
+            Item::Playlist(name) => format!("[{name}]"),
+            Item::File(name) => name.clone(),
+        }
+    }
+
+    fn draw(&self, display: &mut Display) {
+        let font = Font::new(FontSize::MEDIUM, FontFamily::Proportional);
+        let text = |display: &mut Display, text: &str, x: i16, y: i16, color: Rgb<u8>| {
+            display.draw_text(
+                &Text::new(text, font, Point2 { x, y }),
+                color,
+                None::<Rgb<u8>>,
+            );
+        };
+
+        display.erase(BACKGROUND);
+
+        let title = if self.dir.is_empty() {
+            "SD card"
+        } else {
+            &self.dir
+        };
+        text(display, title, 8, 2, DIM);
+
+        for (row, item) in self
+            .items
+            .iter()
+            .enumerate()
+            .skip(self.scroll)
+            .take(VISIBLE_ROWS)
+        {
+            let y = LIST_TOP + (row - self.scroll) as i16 * ROW_HEIGHT;
+            if row == self.selected {
+                display.fill(
+                    &Rect::new(
+                        Point2 { x: 0, y },
+                        Point2 {
+                            x: Display::HORIZONTAL_RESOLUTION - 1,
+                            y: y + ROW_HEIGHT - 1,
+                        },
+                    ),
+                    HIGHLIGHT,
+                );
+            }
+            text(display, &self.label(item), 8, y + 3, FOREGROUND);
+        }
+
+        let on_off = |on: bool| if on { "on" } else { "off" };
+        let footer_y = FOOTER_TOP + 3;
+        text(
+            display,
+            &format!("Loop: {} (X)", on_off(self.looping)),
+            8,
+            footer_y,
+            DIM,
+        );
+        text(
+            display,
+            &format!("Shuffle: {} (Y)", on_off(self.shuffle)),
+            Display::HORIZONTAL_RESOLUTION / 2 + 8,
+            footer_y,
+            DIM,
+        );
+    }
+}
+
+/// Files to play for `path`: the entries of an `.m3u`, or just the video itself
+pub fn load_playlist(path: &str, looping: bool, shuffle: bool) -> Result<Playlist, PlayerError> {
+    let entries = if playlist::is_playlist(path) {
+        let text = fs::read_to_string(path)?;
+        playlist::parse_m3u(&text, playlist::parent(path))
+    } else {
+        vec![path.into()]
+    };
+    build(entries, looping, shuffle)
+}
+
+fn build(entries: Vec<String>, looping: bool, shuffle: bool) -> Result<Playlist, PlayerError> {
+    if entries.is_empty() {
+        return Err(PlayerError::NothingToPlay);
+    }
+
+    let playlist = Playlist::new(entries).looping(looping);
+    Ok(if shuffle {
+        playlist.shuffled(unsafe { vex_sdk::vexSystemHighResTimeGet() } as u32)
+    } else {
+        playlist
+    })
+}
+
+/// Show the videos in `config.dir` until one is picked by touch or controller
+pub async fn choose(
+    display: &mut Display,
+    controller: &Controller,
+    config: &Config,
+) -> Result<Playlist, PlayerError> {
+    let dir = config.dir.as_str();
+    let extensions = media::demuxer_extensions();
+
+    let mut names = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            continue;
+        }
+        if let Some(name) = entry.file_name().to_str() {
+            names.push(String::from(name));
+        }
+    }
+
+    let mut menu = Menu::new(dir, names, &extensions);
+    menu.looping = config.loop_mode == LoopMode::All;
+    menu.shuffle = config.shuffle;
+    if menu.is_empty() {
+        return Err(PlayerError::NothingToPlay);
+    }
+
+    display.set_render_mode(RenderMode::Immediate);
+    menu.draw(display);
+
+    // Whatever is already held (e.g. the button that stopped playback) doesn't count
+    let mut previous = held_buttons(controller);
+    let mut press_count = display.touch_status().press_count;
+    loop {
+        let held = held_buttons(controller);
+        let pressed = |button: Buttons| held.contains(button) && !previous.contains(button);
+        let inputs = [
+            pressed(Buttons::UP).then_some(Input::Up),
+            pressed(Buttons::DOWN).then_some(Input::Down),
+            pressed(Buttons::A).then_some(Input::Select),
+            pressed(Buttons::X).then_some(Input::ToggleLoop),
+            pressed(Buttons::Y).then_some(Input::ToggleShuffle),
+            poll_tap(display, &mut press_count).map(|(x, y)| Input::Tap(x, y)),
+        ];
+        previous = held;
+
+        let mut changed = false;
+        for input in inputs.into_iter().flatten() {
+            if let Some(item) = menu.handle(input) {
+                return menu.playlist(&item);
+            }
+            changed = true;
+        }
+        if changed {
+            menu.draw(display);
+        }
+
+        sleep(Controller::UPDATE_INTERVAL).await;
+    }
+}
@@ -0,0 +1,466 @@
+//! Auto-hiding transport overlay: progress bar, elapsed/total time and play/pause/skip buttons
+//!
+//! Drawn straight into the 0RGB frame buffer before it gets blitted, in coordinates relative to
+//! the video region. Touches are hit-tested in the same coordinates.
+
+use alloc::format;
+use core::time::Duration;
+
+use crate::controls::Command;
+
+const PANEL_HEIGHT: u32 = 52;
+const MARGIN: u32 = 8;
+const BAR_HEIGHT: u32 = 4;
+/// Taps this far above/below the bar still count as hitting it
+const BAR_SLOP: u32 = 8;
+const BUTTON_SIZE: u32 = 16;
+const BUTTON_GAP: u32 = 12;
+/// Smallest video region that still fits everything
+const MIN_WIDTH: u32 = 240;
+
+const HIDE_AFTER: Duration = Duration::from_secs(3);
+
+const PANEL_COLOR: u32 = 0x20_20_20;
+const TRACK_COLOR: u32 = 0x60_60_60;
+const PROGRESS_COLOR: u32 = 0xe0_30_30;
+const LOOP_COLOR: u32 = 0x30_c0_ff;
+const FOREGROUND: u32 = 0xff_ff_ff;
+
+/// What the overlay displays
+#[derive(Debug, Clone, Copy)]
+pub struct Status {
+    pub position: Duration,
+    /// Length of the file, if the container knows it
+    pub duration: Option<Duration>,
+    pub paused: bool,
+    /// A-B repeat points, marked on the progress bar
+    pub loop_points: [Option<Duration>; 2],
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Rect {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+impl Rect {
+    const fn new(x: u32, y: u32, width: u32, height: u32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    fn contains(&self, x: u32, y: u32) -> bool {
+        (self.x..self.x + self.width).contains(&x) && (self.y..self.y + self.height).contains(&y)
+    }
+}
+
+/// Where everything goes within a video region
+struct Geometry {
+    panel: Rect,
+    bar: Rect,
+    previous: Rect,
+    play: Rect,
+    next: Rect,
+    text: (u32, u32),
+}
+
+impl Geometry {
+    fn new(width: u32, height: u32) -> Option<Self> {
+        if width < MIN_WIDTH || height < PANEL_HEIGHT {
+            return None;
+        }
+
+        // Bar, then the time on its own row (`h:mm:ss / h:mm:ss` is wider than the space left of
+        // the buttons in a narrow region), then the buttons
+        let top = height - PANEL_HEIGHT;
+        let text_y = top + 16;
+        let buttons_y = top + 28;
+        let buttons_x = (width - (BUTTON_SIZE * 3 + BUTTON_GAP * 2)) / 2;
+        let button = |index: u32| {
+            Rect::new(
+                buttons_x + index * (BUTTON_SIZE + BUTTON_GAP),
+                buttons_y,
+                BUTTON_SIZE,
+                BUTTON_SIZE,
+            )
+        };
+
+        Some(Self {
+            panel: Rect::new(0, top, width, PANEL_HEIGHT),
+            bar: Rect::new(MARGIN, top + 8, width - MARGIN * 2, BAR_HEIGHT),
+            previous: button(0),
+            play: button(1),
+            next: button(2),
+            text: (MARGIN, text_y),
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Overlay {
+    visible_until: Option<Duration>,
+}
+
+impl Overlay {
+    /// Stays up while paused, otherwise hides a few seconds after the last touch
+    pub fn is_visible(&self, now: Duration, paused: bool) -> bool {
+        paused || self.visible_until.is_some_and(|until| now < until)
+    }
+
+    /// Handle a tap at `(x, y)` within a `width`x`height` video region. A tap while hidden only
+    /// brings the overlay up
+    pub fn touch(
+        &mut self,
+        (x, y): (i32, i32),
+        (width, height): (u32, u32),
+        status: Status,
+        now: Duration,
+    ) -> Option<Command> {
+        let was_visible = self.is_visible(now, status.paused);
+        self.visible_until = Some(now + HIDE_AFTER);
+
+        let geometry = Geometry::new(width, height)?;
+        let (x, y) = (u32::try_from(x).ok()?, u32::try_from(y).ok()?);
+        if !was_visible || !geometry.panel.contains(x, y) {
+            return None;
+        }
+
+        let bar = geometry.bar;
+        let bar_target = Rect::new(
+            bar.x,
+            bar.y.saturating_sub(BAR_SLOP),
+            bar.width,
+            bar.height + BAR_SLOP * 2,
+        );
+        if bar_target.contains(x, y) {
+            let duration = status.duration?;
+            return Some(Command::SeekTo(duration * (x - bar.x) / bar.width));
+        }
+
+        if geometry.previous.contains(x, y) {
+            Some(Command::PreviousFile)
+        } else if geometry.play.contains(x, y) {
+            Some(Command::TogglePause)
+        } else if geometry.next.contains(x, y) {
+            Some(Command::NextFile)
+        } else {
+            None
+        }
+    }
+
+    /// Draw into `pixels` (0RGB, `width`x`height`, no padding) if visible. Returns whether
+    /// anything was drawn
+    pub fn draw(
+        &self,
+        pixels: &mut [u32],
+        (width, height): (u32, u32),
+        status: Status,
+        now: Duration,
+    ) -> bool {
+        if !self.is_visible(now, status.paused) {
+            return false;
+        }
+        let Some(geometry) = Geometry::new(width, height) else {
+            return false;
+        };
+
+        let mut canvas = Canvas { pixels, width };
+        canvas.fill(geometry.panel, PANEL_COLOR);
+
+        let bar = geometry.bar;
+        canvas.fill(bar, TRACK_COLOR);
+        if let Some(duration) = status.duration.filter(|duration| !duration.is_zero()) {
+            let progress = status.position.min(duration).as_millis() as u64 * bar.width as u64
+                / duration.as_millis().max(1) as u64;
+            canvas.fill(
+                Rect::new(bar.x, bar.y, progress as u32, bar.height),
+                PROGRESS_COLOR,
+            );
+
+            for point in status.loop_points.into_iter().flatten() {
+                let offset = point.min(duration).as_millis() as u64 * (bar.width - 2) as u64
+                    / duration.as_millis().max(1) as u64;
+                canvas.fill(
+                    Rect::new(bar.x + offset as u32, bar.y - 3, 2, bar.height + 6),
+                    LOOP_COLOR,
+                );
+            }
+        }
+
+        // |<  >|  and either > or ||
+        let Rect { x, y, height, .. } = geometry.previous;
+        canvas.fill(Rect::new(x, y, 3, height), FOREGROUND);
+        canvas.triangle(Rect::new(x + 3, y, BUTTON_SIZE - 3, height), false);
+
+        let Rect { x, y, height, .. } = geometry.next;
+        canvas.triangle(Rect::new(x, y, BUTTON_SIZE - 3, height), true);
+        canvas.fill(Rect::new(x + BUTTON_SIZE - 3, y, 3, height), FOREGROUND);
+
+        let play = geometry.play;
+        if status.paused {
+            canvas.triangle(play, true);
+        } else {
+            canvas.fill(Rect::new(play.x + 2, play.y, 4, play.height), FOREGROUND);
+            canvas.fill(
+                Rect::new(play.x + play.width - 6, play.y, 4, play.height),
+                FOREGROUND,
+            );
+        }
+
+        let total = status.duration.map_or("-:--".into(), format_time);
+        let text = format!("{} / {total}", format_time(status.position));
+        canvas.text(geometry.text, &text);
+
+        true
+    }
+}
+
+/// `m:ss`, or `h:mm:ss` past an hour
+fn format_time(time: Duration) -> alloc::string::String {
+    let secs = time.as_secs();
+    let (hours, minutes, secs) = (secs / 3600, secs / 60 % 60, secs % 60);
+    if hours > 0 {
+        format!("{hours}:{minutes:02}:{secs:02}")
+    } else {
+        format!("{minutes}:{secs:02}")
+    }
+}
+
+struct Canvas<'a> {
+    pixels: &'a mut [u32],
+    width: u32,
+}
+
+impl Canvas<'_> {
+    fn fill(&mut self, rect: Rect, color: u32) {
+        for y in rect.y..rect.y + rect.height {
+            let row = (y * self.width + rect.x) as usize;
+            self.pixels[row..row + rect.width as usize].fill(color);
+        }
+    }
+
+    /// Isosceles triangle filling `rect`, pointing right or left
+    fn triangle(&mut self, rect: Rect, right: bool) {
+        let half = rect.height / 2;
+        for row in 0..rect.height {
+            let from_middle = row.abs_diff(half);
+            let len = rect.width * (half - from_middle.min(half)) / half.max(1);
+            let x = if right {
+                rect.x
+            } else {
+                rect.x + rect.width - len
+            };
+            self.fill(Rect::new(x, rect.y + row, len, 1), FOREGROUND);
+        }
+    }
+
+    fn text(&mut self, (x, y): (u32, u32), text: &str) {
+        for (index, char) in text.chars().enumerate() {
+            let Some(glyph) = glyph(char) else {
+                continue;
+            };
+
+            let left = x + index as u32 * (GLYPH_WIDTH + 1);
+            for (row, bits) in glyph.iter().enumerate() {
+                for column in 0..GLYPH_WIDTH {
+                    if bits & (1 << (GLYPH_WIDTH - 1 - column)) != 0 {
+                        let index = (y + row as u32) * self.width + left + column;
+                        self.pixels[index as usize] = FOREGROUND;
+                    }
+                }
+            }
+        }
+    }
+}
+
+const GLYPH_WIDTH: u32 = 5;
+const GLYPH_HEIGHT: u32 = 7;
+
+/// 5x7 glyphs for the handful of characters timestamps need, one byte per row
+fn glyph(char: char) -> Option<[u8; GLYPH_HEIGHT as usize]> {
+    Some(match char {
+        '0' => [0x0e, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0e],
+        '1' => [0x04, 0x0c, 0x04, 0x04, 0x04, 0x04, 0x0e],
+        '2' => [0x0e, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1f],
+        '3' => [0x1f, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0e],
+        '4' => [0x02, 0x06, 0x0a, 0x12, 0x1f, 0x02, 0x02],
+        '5' => [0x1f, 0x10, 0x1e, 0x01, 0x01, 0x11, 0x0e],
+        '6' => [0x06, 0x08, 0x10, 0x1e, 0x11, 0x11, 0x0e],
+        '7' => [0x1f, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0e, 0x11, 0x11, 0x0e, 0x11, 0x11, 0x0e],
+        '9' => [0x0e, 0x11, 0x11, 0x0f, 0x01, 0x02, 0x0c],
+        ':' => [0x00, 0x0c, 0x0c, 0x00, 0x0c, 0x0c, 0x00],
+        '/' => [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00],
+        '-' => [0x00, 0x00, 0x00, 0x1f, 0x00, 0x00, 0x00],
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: (u32, u32) = (400, 200);
+    const NOW: Duration = Duration::from_secs(10);
+
+    fn status(duration: Option<Duration>, paused: bool) -> Status {
+        Status {
+            position: Duration::ZERO,
+            duration,
+            paused,
+            loop_points: [None; 2],
+        }
+    }
+
+    fn center(rect: Rect) -> (i32, i32) {
+        (
+            (rect.x + rect.width / 2) as i32,
+            (rect.y + rect.height / 2) as i32,
+        )
+    }
+
+    /// An overlay that's already up
+    fn shown() -> Overlay {
+        let mut overlay = Overlay::default();
+        overlay.touch((0, 0), SIZE, status(None, false), NOW);
+        overlay
+    }
+
+    #[test]
+    fn text_clears_buttons() {
+        let overlaps = |a: Rect, b: Rect| {
+            a.x < b.x + b.width
+                && b.x < a.x + a.width
+                && a.y < b.y + b.height
+                && b.y < a.y + a.height
+        };
+
+        let widest = "99:59:59 / 99:59:59".len() as u32 * (GLYPH_WIDTH + 1) - 1;
+        for width in [MIN_WIDTH, 320, 480] {
+            let geometry = Geometry::new(width, PANEL_HEIGHT).unwrap();
+            let text = Rect::new(geometry.text.0, geometry.text.1, widest, GLYPH_HEIGHT);
+            assert!(text.x + text.width <= width);
+            assert!(text.y + text.height <= PANEL_HEIGHT);
+            for other in [
+                geometry.bar,
+                geometry.previous,
+                geometry.play,
+                geometry.next,
+            ] {
+                assert!(!overlaps(text, other), "{width}: {other:?}");
+            }
+        }
+        assert!(Geometry::new(MIN_WIDTH - 1, 200).is_none());
+        assert!(Geometry::new(MIN_WIDTH, PANEL_HEIGHT - 1).is_none());
+    }
+
+    #[test]
+    fn tap_while_hidden_only_shows() {
+        let play = center(Geometry::new(SIZE.0, SIZE.1).unwrap().play);
+        let mut overlay = Overlay::default();
+        assert!(!overlay.is_visible(NOW, false));
+
+        assert_eq!(overlay.touch(play, SIZE, status(None, false), NOW), None);
+        assert!(overlay.is_visible(NOW, false));
+        assert_eq!(
+            overlay.touch(play, SIZE, status(None, false), NOW),
+            Some(Command::TogglePause)
+        );
+
+        // Hidden again once it's been left alone
+        let later = NOW + HIDE_AFTER * 2;
+        assert!(!overlay.is_visible(later, false));
+        assert_eq!(overlay.touch(play, SIZE, status(None, false), later), None);
+
+        // Always up while paused
+        let mut overlay = Overlay::default();
+        assert_eq!(
+            overlay.touch(play, SIZE, status(None, true), NOW),
+            Some(Command::TogglePause)
+        );
+    }
+
+    #[test]
+    fn bar_seeks_proportionally() {
+        let bar = Geometry::new(SIZE.0, SIZE.1).unwrap().bar;
+        let duration = Some(Duration::from_secs(100));
+        let mut overlay = shown();
+        let mut tap = |x: u32, y: u32| {
+            overlay.touch((x as i32, y as i32), SIZE, status(duration, false), NOW)
+        };
+
+        assert_eq!(tap(bar.x, bar.y), Some(Command::SeekTo(Duration::ZERO)));
+        assert_eq!(
+            tap(bar.x + bar.width / 4, bar.y + 1),
+            Some(Command::SeekTo(Duration::from_secs(25)))
+        );
+        assert_eq!(
+            tap(bar.x + bar.width - 1, bar.y),
+            Some(Command::SeekTo(
+                Duration::from_secs(100) * (bar.width - 1) / bar.width
+            ))
+        );
+
+        // Within the slop above & below
+        let middle = Some(Command::SeekTo(Duration::from_secs(50)));
+        let x = bar.x + bar.width / 2;
+        assert_eq!(tap(x, bar.y - BAR_SLOP), middle);
+        assert_eq!(tap(x, bar.y + bar.height + BAR_SLOP - 1), middle);
+        assert_eq!(tap(x, bar.y + bar.height + BAR_SLOP), None);
+        // Left & right of the bar
+        assert_eq!(tap(bar.x - 1, bar.y), None);
+        assert_eq!(tap(bar.x + bar.width, bar.y), None);
+    }
+
+    #[test]
+    fn buttons() {
+        let geometry = Geometry::new(SIZE.0, SIZE.1).unwrap();
+        let mut overlay = shown();
+        let mut tap = |(x, y)| overlay.touch((x, y), SIZE, status(None, false), NOW);
+
+        assert_eq!(tap(center(geometry.previous)), Some(Command::PreviousFile));
+        assert_eq!(tap(center(geometry.play)), Some(Command::TogglePause));
+        assert_eq!(tap(center(geometry.next)), Some(Command::NextFile));
+
+        // Between buttons, above the panel & outside the region
+        let (x, y) = center(geometry.play);
+        assert_eq!(tap((geometry.play.x as i32 - 1, y)), None);
+        assert_eq!(tap((x, geometry.panel.y as i32 - 1)), None);
+        assert_eq!(tap((-1, y)), None);
+        assert_eq!(tap((x, SIZE.1 as i32)), None);
+    }
+
+    #[test]
+    fn no_duration() {
+        let bar = Geometry::new(SIZE.0, SIZE.1).unwrap().bar;
+        let mut overlay = shown();
+        // Nothing to seek into, but the overlay stays up
+        assert_eq!(
+            overlay.touch(center(bar), SIZE, status(None, false), NOW),
+            None
+        );
+        assert!(overlay.is_visible(NOW, false));
+    }
+
+    #[test]
+    fn too_small() {
+        let mut overlay = shown();
+        assert_eq!(
+            overlay.touch((100, 100), (MIN_WIDTH - 1, 200), status(None, true), NOW),
+            None
+        );
+    }
+
+    #[test]
+    fn format_time() {
+        assert_eq!(super::format_time(Duration::from_millis(59_999)), "0:59");
+        assert_eq!(super::format_time(Duration::from_secs(61)), "1:01");
+        assert_eq!(super::format_time(Duration::from_secs(3600 + 5)), "1:00:05");
+    }
+}
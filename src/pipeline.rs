@@ -0,0 +1,321 @@
+//! Decode-ahead: a producer task demuxes, decodes & converts frames into a [`FrameQueue`] while
+//! the display side takes them off at their presentation time
+//!
+//! Both sides run on vexide's executor and share state through a `RefCell`, which is only ever
+//! borrowed between awaits. The producer gets to work whenever the display side is sleeping
+//! until a deadline.
+
+use alloc::{boxed::Box, rc::Rc, vec::Vec};
+use core::{cell::RefCell, future::poll_fn, task::Poll, time::Duration};
+
+use vexide::{devices::display::Display, io::println, prelude::sleep};
+
+use crate::{
+    color,
+    config::Config,
+    error::PlayerError,
+    layout::{Layout, Region},
+    media::{self, Frame},
+    player::Player,
+    queue::FrameQueue,
+    render,
+    scale::{self, ScaleFilter, Scaler},
+};
+
+const SCREEN_PIXELS: usize =
+    Display::HORIZONTAL_RESOLUTION as usize * Display::VERTICAL_RESOLUTION as usize;
+
+/// How long either side waits before checking the queue again, when there's nothing it can do
+pub const IDLE_POLL: Duration = Duration::from_millis(2);
+
+/// A converted frame
+pub struct Slot {
+    /// 0RGB, stride `region.width`; only the first `region.area()` pixels are used
+    pub pixels: Box<[u32]>,
+    /// Where on screen it goes
+    pub region: Region,
+    /// When to show it, on the timeline that carries on across loops
+    pub pts: Option<Duration>,
+    /// Stream time
+    pub position: Duration,
+}
+
+pub struct Shared {
+    pub player: Player,
+    pub queue: FrameQueue<Slot>,
+    /// The player ran out of frames; cleared by seeking
+    pub finished: bool,
+    /// What stopped the producer, for the display side to report
+    pub error: Option<PlayerError>,
+}
+
+impl Shared {
+    pub fn new(player: Player, buffers: Vec<Slot>) -> Self {
+        Self {
+            player,
+            queue: FrameQueue::new(buffers),
+            finished: false,
+            error: None,
+        }
+    }
+
+    /// Seek, throwing away everything that was decoded ahead
+    pub fn seek_to(&mut self, target: Duration) -> Result<(), PlayerError> {
+        self.player.seek_to(target)?;
+        self.queue.clear();
+        self.finished = false;
+        Ok(())
+    }
+}
+
+/// How many full-screen buffers `config` allows for. Never fewer than two: one on screen and one
+/// being converted into
+pub fn buffer_count(config: &Config) -> usize {
+    let frame_bytes = SCREEN_PIXELS * size_of::<u32>();
+    config
+        .queue_depth
+        .min(config.queue_memory / frame_bytes)
+        .max(2)
+}
+
+pub fn allocate(count: usize) -> Result<Vec<Slot>, PlayerError> {
+    let mut slots = Vec::new();
+    slots
+        .try_reserve_exact(count)
+        .map_err(|_| PlayerError::Alloc)?;
+    for _ in 0..count {
+        let mut pixels = Vec::new();
+        pixels
+            .try_reserve_exact(SCREEN_PIXELS)
+            .map_err(|_| PlayerError::Alloc)?;
+        pixels.resize(SCREEN_PIXELS, 0);
+
+        slots.push(Slot {
+            pixels: pixels.into_boxed_slice(),
+            region: Region::default(),
+            pts: None,
+            position: Duration::ZERO,
+        });
+    }
+    Ok(slots)
+}
+
+/// Keeps the queue topped up until dropped; errors end up in [`Shared::error`]
+pub async fn produce(shared: Rc<RefCell<Shared>>, config: Config) {
+    let mut frame = match Frame::new() {
+        Ok(frame) => frame,
+        Err(err) => {
+            shared.borrow_mut().error = Some(err);
+            return;
+        }
+    };
+    let sample_aspect = {
+        let shared = shared.borrow();
+        let sample_aspect = unsafe { (*shared.player.stream().codecpar).sample_aspect_ratio };
+        (sample_aspect.num, sample_aspect.den)
+    };
+    let mut converter = Converter::new(sample_aspect);
+
+    loop {
+        let produced = produce_one(
+            &mut shared.borrow_mut(),
+            &mut frame,
+            &mut converter,
+            &config,
+        );
+        match produced {
+            Ok(true) => yield_now().await,
+            Ok(false) => sleep(IDLE_POLL).await,
+            Err(err) => {
+                shared.borrow_mut().error = Some(err);
+                return;
+            }
+        }
+    }
+}
+
+/// Decode & convert one frame into a free buffer. `false` if there was nothing to do
+fn produce_one(
+    shared: &mut Shared,
+    frame: &mut Frame,
+    converter: &mut Converter,
+    config: &Config,
+) -> Result<bool, PlayerError> {
+    if shared.finished {
+        return Ok(false);
+    }
+    let Some(mut slot) = shared.queue.take_free() else {
+        return Ok(false);
+    };
+
+    let converted = shared.player.next_frame(frame).and_then(|decoded| {
+        if !decoded {
+            return Ok(None);
+        }
+        let lowres = shared.player.lowres();
+        converter
+            .convert(frame, lowres, config, &mut slot.pixels)
+            .map(Some)
+    });
+
+    let region = match converted {
+        Ok(Some(region)) => region,
+        Ok(None) => {
+            shared.queue.recycle(slot);
+            shared.finished = true;
+            return Ok(false);
+        }
+        Err(err) => {
+            shared.queue.recycle(slot);
+            return Err(err);
+        }
+    };
+
+    slot.region = region;
+    slot.pts = shared.player.presentation_time(frame);
+    slot.position = shared.player.position();
+    frame.unref();
+    shared.queue.push(slot);
+    Ok(true)
+}
+
+/// Scalers & layout, kept around between frames of the same size
+struct Converter {
+    /// The stream's `sample_aspect_ratio` as `(num, den)`
+    sample_aspect: (i32, i32),
+    luma_scaler: Option<Scaler>,
+    blue_scaler: Option<Scaler>,
+    red_scaler: Option<Scaler>,
+    /// Keyed by frame size & `lowres`
+    layout: Option<((usize, usize, u8), Layout)>,
+}
+
+impl Converter {
+    fn new(sample_aspect: (i32, i32)) -> Self {
+        Self {
+            sample_aspect,
+            luma_scaler: None,
+            blue_scaler: None,
+            red_scaler: None,
+            layout: None,
+        }
+    }
+
+    /// Scale & convert `frame`, decoded at 1/2^`lowres` size, into `pixels`, returning where on
+    /// screen it goes
+    fn convert(
+        &mut self,
+        frame: &Frame,
+        lowres: u8,
+        config: &Config,
+        pixels: &mut [u32],
+    ) -> Result<Region, PlayerError> {
+        let width = frame.width();
+        let height = frame.height();
+
+        let Layout { src, dst } = match self.layout {
+            Some((key, layout)) if key == (width, height, lowres) => layout,
+            _ => {
+                // Laid out at full size, so dropping resolution doesn't move or resize the picture
+                let new_layout = Layout::compute(
+                    config.fit_mode,
+                    ((width as u32) << lowres, (height as u32) << lowres),
+                    self.sample_aspect,
+                    (
+                        Display::HORIZONTAL_RESOLUTION as u32,
+                        Display::VERTICAL_RESOLUTION as u32,
+                    ),
+                )
+                .lowres(lowres, (width as u32, height as u32));
+                println!("Layout for {width}x{height} (lowres {lowres}): {new_layout:?}");
+                self.layout = Some(((width, height, lowres), new_layout));
+                new_layout
+            }
+        };
+        let dst_width = dst.width as usize;
+        let dst_height = dst.height as usize;
+
+        let format = frame.format();
+        let planes =
+            [0, 1, 2].map(|index| frame.plane(index).map(|plane| (plane.data, plane.stride)));
+        let window = (
+            src.x as usize,
+            src.y as usize,
+            src.width as usize,
+            src.height as usize,
+        );
+        let Some((desc, [luma_sampler, blue_sampler, red_sampler])) =
+            media::describe(format).and_then(|desc| Some((desc, desc.samplers(planes, window)?)))
+        else {
+            return Err(PlayerError::UnsupportedPixelFormat(format));
+        };
+
+        let (luma_width, luma_height) = luma_sampler.size();
+        let luma_scaler = scale::cached(
+            &mut self.luma_scaler,
+            config.scale_filter,
+            scale::Axis::new(luma_width, dst_width),
+            scale::Axis::new(luma_height, dst_height),
+        );
+
+        // Chroma is lined up with luma according to its siting, and always interpolated; picking
+        // the nearest subsampled chroma sample is what fringes sharp edges
+        let (chroma_horizontal, chroma_vertical) =
+            desc.chroma_axes(frame.chroma_location(), window, (dst_width, dst_height));
+        let chroma_filter = match config.scale_filter {
+            ScaleFilter::Nearest => ScaleFilter::Bilinear,
+            filter => filter,
+        };
+        // One scaler per chroma plane, since the fused pass keeps all three row caches live
+        let blue_scaler = scale::cached(
+            &mut self.blue_scaler,
+            chroma_filter,
+            chroma_horizontal,
+            chroma_vertical,
+        );
+        let red_scaler = scale::cached(
+            &mut self.red_scaler,
+            chroma_filter,
+            chroma_horizontal,
+            chroma_vertical,
+        );
+
+        let conversion = color::Conversion::new(
+            config
+                .color_matrix
+                .or(frame.color_matrix())
+                .unwrap_or_else(|| color::Matrix::default_for_height(height << lowres)),
+            config
+                .color_range
+                .or(frame.color_range())
+                .or(desc.full_range.then_some(color::Range::Full))
+                .unwrap_or_default(),
+        );
+
+        // Scale & convert to 0RGB (8bit) in one pass
+        render::scale_convert(
+            [luma_scaler, blue_scaler, red_scaler],
+            [&luma_sampler, &blue_sampler, &red_sampler],
+            &conversion,
+            bytemuck::cast_slice_mut(&mut pixels[..dst.area()]),
+            dst_width,
+        );
+
+        Ok(dst)
+    }
+}
+
+/// Let the other tasks run before carrying on
+async fn yield_now() {
+    let mut yielded = false;
+    poll_fn(|cx| {
+        if yielded {
+            Poll::Ready(())
+        } else {
+            yielded = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    })
+    .await
+}
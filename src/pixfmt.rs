@@ -0,0 +1,505 @@
+//! Generic access to the Y/Cb/Cr components of decoder output
+//!
+//! Every supported pixel format is described by a [`FormatDesc`], which is enough to hand out one
+//! 8-bit [`Sampler`] per component regardless of plane layout, subsampling or bit depth.
+
+use fixed::types::I48F16;
+
+use crate::scale::{Axis, RowSource};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlaneLayout {
+    /// Y, Cb & Cr each in their own plane (yuv4xxp)
+    Planar,
+    /// Y plane, then one plane of interleaved chroma pairs (nv12/nv21)
+    SemiPlanar { cb_first: bool },
+    /// Luma only, chroma is implicitly neutral
+    Gray,
+}
+
+/// Where subsampled chroma sits relative to the luma samples it covers (`AVChromaLocation`)
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ChromaLocation {
+    /// Co-sited with the left luma column, halfway between rows (MPEG-2/H.264/VP9)
+    #[default]
+    Left,
+    /// Centered between luma samples (MPEG-1/JPEG)
+    Center,
+    /// Co-sited with the top-left luma sample (BT.2020)
+    TopLeft,
+    Top,
+    BottomLeft,
+    Bottom,
+}
+
+impl ChromaLocation {
+    /// `(x, y)` of the first chroma sample within its block of luma samples, in half blocks:
+    /// 0 is on the first luma sample, 1 is centered, 2 is on the last
+    const fn siting(self) -> (u8, u8) {
+        match self {
+            Self::Left => (0, 1),
+            Self::Center => (1, 1),
+            Self::TopLeft => (0, 0),
+            Self::Top => (1, 0),
+            Self::BottomLeft => (0, 2),
+            Self::Bottom => (1, 2),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FormatDesc {
+    pub layout: PlaneLayout,
+    pub log2_chroma_w: u8,
+    pub log2_chroma_h: u8,
+    /// Bits per sample; anything over 8 is stored as little-endian u16
+    pub depth: u8,
+    /// Legacy `yuvj*` formats imply full range
+    pub full_range: bool,
+}
+
+impl FormatDesc {
+    pub const fn planar(log2_chroma_w: u8, log2_chroma_h: u8, depth: u8) -> Self {
+        Self {
+            layout: PlaneLayout::Planar,
+            log2_chroma_w,
+            log2_chroma_h,
+            depth,
+            full_range: false,
+        }
+    }
+
+    pub const fn full_range(mut self) -> Self {
+        self.full_range = true;
+        self
+    }
+
+    /// `(x, y, width, height)` of the chroma samples covering the given luma window
+    pub fn chroma_window(
+        &self,
+        (x, y, width, height): (usize, usize, usize, usize),
+    ) -> (usize, usize, usize, usize) {
+        let (sw, sh) = (self.log2_chroma_w, self.log2_chroma_h);
+        let (cx, cy) = (x >> sw, y >> sh);
+        let end_x = (x + width).div_ceil(1 << sw);
+        let end_y = (y + height).div_ceil(1 << sh);
+        (cx, cy, (end_x - cx).max(1), (end_y - cy).max(1))
+    }
+
+    /// `(horizontal, vertical)` axes for scaling the chroma window to `dst`, so each output pixel
+    /// samples chroma at the same spot in the picture as luma rather than at the chroma grid's
+    pub fn chroma_axes(
+        &self,
+        location: ChromaLocation,
+        window: (usize, usize, usize, usize),
+        (dst_width, dst_height): (usize, usize),
+    ) -> (Axis, Axis) {
+        let (x, y, width, height) = window;
+        let (cx, cy, cw, ch) = self.chroma_window(window);
+        let (siting_x, siting_y) = location.siting();
+
+        (
+            chroma_axis(
+                (x, width),
+                (cx, cw),
+                dst_width,
+                self.log2_chroma_w,
+                siting_x,
+            ),
+            chroma_axis(
+                (y, height),
+                (cy, ch),
+                dst_height,
+                self.log2_chroma_h,
+                siting_y,
+            ),
+        )
+    }
+
+    /// One sampler per component (Y, Cb, Cr), each cropped to the given luma window
+    ///
+    /// `planes` are the frame's `(data, stride)` pairs; `None` if a plane the layout needs is missing
+    pub fn samplers<'a>(
+        &self,
+        planes: [Option<(&'a [u8], usize)>; 3],
+        window: (usize, usize, usize, usize),
+    ) -> Option<[Sampler<'a>; 3]> {
+        let chroma = self.chroma_window(window);
+        let component = |(data, stride): (&'a [u8], usize)| {
+            if self.depth > 8 {
+                Component::Plane16 {
+                    data,
+                    stride,
+                    shift: self.depth - 8,
+                }
+            } else {
+                Component::Plane8 { data, stride }
+            }
+        };
+
+        let luma = Sampler::new(component(planes[0]?), window);
+        let [blue, red] = match self.layout {
+            PlaneLayout::Planar => [
+                Sampler::new(component(planes[1]?), chroma),
+                Sampler::new(component(planes[2]?), chroma),
+            ],
+            PlaneLayout::SemiPlanar { cb_first } => {
+                let (data, stride) = planes[1]?;
+                let interleaved = |offset| Component::Interleaved8 {
+                    data,
+                    stride,
+                    offset,
+                };
+                let (cb, cr) = if cb_first { (0, 1) } else { (1, 0) };
+                [
+                    Sampler::new(interleaved(cb), chroma),
+                    Sampler::new(interleaved(cr), chroma),
+                ]
+            }
+            PlaneLayout::Gray => [
+                Sampler::new(Component::Constant(128), chroma),
+                Sampler::new(Component::Constant(128), chroma),
+            ],
+        };
+
+        Some([luma, blue, red])
+    }
+}
+
+/// Map output pixels through luma positions onto the chroma grid
+fn chroma_axis(
+    (luma_start, luma_len): (usize, usize),
+    (chroma_start, chroma_len): (usize, usize),
+    dst_len: usize,
+    log2_subsampling: u8,
+    siting: u8,
+) -> Axis {
+    let half = I48F16::ONE / 2;
+    let block = I48F16::from_num(1u32 << log2_subsampling);
+    // Luma position of chroma sample 0
+    let phase = I48F16::from_num(siting) * (block - I48F16::ONE) / 2;
+
+    // Output pixel i covers luma position `luma_start + (i + 0.5) * luma_scale - 0.5`, which is
+    // chroma position `(luma - phase) / block`; shift that into the cropped chroma window
+    let luma_scale = I48F16::from_num(luma_len) / I48F16::from_num(dst_len.max(1));
+    let offset = (I48F16::from_num(luma_start) - half - phase) / block
+        - I48F16::from_num(chroma_start)
+        + half;
+
+    Axis {
+        src_len: chroma_len,
+        dst_len,
+        scale: luma_scale / block,
+        offset,
+    }
+}
+
+/// How one component's samples are stored
+#[derive(Debug, Clone, Copy)]
+pub enum Component<'a> {
+    /// One byte per sample
+    Plane8 { data: &'a [u8], stride: usize },
+    /// Byte pairs, this component at `offset` in each (nv12 chroma)
+    Interleaved8 {
+        data: &'a [u8],
+        stride: usize,
+        offset: usize,
+    },
+    /// Little-endian u16 per sample, reduced to 8 bits by dropping `shift` bits (with rounding)
+    Plane16 {
+        data: &'a [u8],
+        stride: usize,
+        shift: u8,
+    },
+    /// Not stored at all
+    Constant(u8),
+}
+
+/// 8-bit view of a window of one component
+#[derive(Debug, Clone, Copy)]
+pub struct Sampler<'a> {
+    component: Component<'a>,
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+}
+
+impl<'a> Sampler<'a> {
+    pub fn new(
+        component: Component<'a>,
+        (x, y, width, height): (usize, usize, usize, usize),
+    ) -> Self {
+        Self {
+            component,
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    pub fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+}
+
+impl RowSource for Sampler<'_> {
+    fn row<'s>(&'s self, y: usize, scratch: &'s mut [u8]) -> &'s [u8] {
+        let (x, y, width) = (self.x, self.y + y, self.width);
+        let out = &mut scratch[..width];
+
+        match self.component {
+            Component::Plane8 { data, stride } => return &data[y * stride + x..][..width],
+            Component::Interleaved8 {
+                data,
+                stride,
+                offset,
+            } => {
+                let row = &data[y * stride + x * 2 + offset..];
+                for (out, pair) in out.iter_mut().zip(row.iter().step_by(2)) {
+                    *out = *pair;
+                }
+            }
+            Component::Plane16 {
+                data,
+                stride,
+                shift,
+            } => {
+                let row = &data[y * stride + x * 2..][..width * 2];
+                let round = 1 << (shift - 1);
+                for (out, sample) in out.iter_mut().zip(row.as_chunks::<2>().0) {
+                    let sample = u16::from_le_bytes(*sample) as u32;
+                    *out = ((sample + round) >> shift).min(u8::MAX as u32) as u8;
+                }
+            }
+            Component::Constant(value) => out.fill(value),
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+
+    /// Every row of `sampler`
+    fn read(sampler: &Sampler) -> Vec<Vec<u8>> {
+        let (width, height) = sampler.size();
+        let mut scratch = [0; 64];
+        (0..height)
+            .map(|y| sampler.row(y, &mut scratch)[..width].to_vec())
+            .collect()
+    }
+
+    // 4x2 luma with a stride of 6, so padding would show up if it were read
+    const LUMA: [u8; 12] = [1, 2, 3, 4, 0, 0, 5, 6, 7, 8, 0, 0];
+
+    #[test]
+    fn semi_planar() {
+        let chroma = [100, 200, 101, 201, 0, 0];
+        let nv12 = FormatDesc {
+            layout: PlaneLayout::SemiPlanar { cb_first: true },
+            ..FormatDesc::planar(1, 1, 8)
+        };
+        let planes = [Some((&LUMA[..], 6)), Some((&chroma[..], 6)), None];
+
+        let [luma, blue, red] = nv12.samplers(planes, (0, 0, 4, 2)).unwrap();
+        assert_eq!(read(&luma), [[1, 2, 3, 4], [5, 6, 7, 8]]);
+        assert_eq!(read(&blue), [[100, 101]]);
+        assert_eq!(read(&red), [[200, 201]]);
+
+        let nv21 = FormatDesc {
+            layout: PlaneLayout::SemiPlanar { cb_first: false },
+            ..nv12
+        };
+        let [_, blue, red] = nv21.samplers(planes, (0, 0, 4, 2)).unwrap();
+        assert_eq!(read(&blue), [[200, 201]]);
+        assert_eq!(read(&red), [[100, 101]]);
+
+        // Cropping to the right half lands on the second chroma pair
+        let [luma, blue, red] = nv12.samplers(planes, (2, 0, 2, 2)).unwrap();
+        assert_eq!(read(&luma), [[3, 4], [7, 8]]);
+        assert_eq!(read(&blue), [[101]]);
+        assert_eq!(read(&red), [[201]]);
+    }
+
+    #[test]
+    fn planar_422() {
+        let blue = [10, 11, 12, 13];
+        let red = [20, 21, 22, 23];
+        let desc = FormatDesc::planar(1, 0, 8);
+        let planes = [
+            Some((&LUMA[..], 6)),
+            Some((&blue[..], 2)),
+            Some((&red[..], 2)),
+        ];
+
+        let [luma, blue, red] = desc.samplers(planes, (0, 0, 4, 2)).unwrap();
+        assert_eq!(read(&luma), [[1, 2, 3, 4], [5, 6, 7, 8]]);
+        assert_eq!(read(&blue), [[10, 11], [12, 13]]);
+        assert_eq!(read(&red), [[20, 21], [22, 23]]);
+
+        let [_, blue, _] = desc.samplers(planes, (1, 1, 2, 1)).unwrap();
+        assert_eq!(read(&blue), [[12, 13]]);
+    }
+
+    #[test]
+    fn planar_444() {
+        let blue: Vec<u8> = LUMA.iter().map(|sample| sample + 10).collect();
+        let red: Vec<u8> = LUMA.iter().map(|sample| sample + 20).collect();
+        let desc = FormatDesc::planar(0, 0, 8);
+        let planes = [
+            Some((&LUMA[..], 6)),
+            Some((&blue[..], 6)),
+            Some((&red[..], 6)),
+        ];
+
+        let [luma, blue, red] = desc.samplers(planes, (1, 1, 2, 1)).unwrap();
+        assert_eq!(read(&luma), [[6, 7]]);
+        assert_eq!(read(&blue), [[16, 17]]);
+        assert_eq!(read(&red), [[26, 27]]);
+
+        // Missing a plane the layout needs
+        assert!(
+            desc.samplers([planes[0], planes[1], None], (0, 0, 4, 2))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn gray() {
+        let desc = FormatDesc {
+            layout: PlaneLayout::Gray,
+            ..FormatDesc::planar(0, 0, 8)
+        };
+        let [luma, blue, red] = desc
+            .samplers([Some((&LUMA[..], 6)), None, None], (1, 0, 3, 2))
+            .unwrap();
+        assert_eq!(read(&luma), [[2, 3, 4], [6, 7, 8]]);
+        assert_eq!(read(&blue), [[128; 3]; 2]);
+        assert_eq!(read(&red), [[128; 3]; 2]);
+    }
+
+    #[test]
+    fn ten_bit() {
+        let samples: [u16; 8] = [0, 1, 2, 5, 6, 512, 1021, 1023];
+        let luma: Vec<u8> = samples
+            .iter()
+            .flat_map(|sample| sample.to_le_bytes())
+            .collect();
+        let chroma: Vec<u8> = [300u16, 1022, 2, 3]
+            .iter()
+            .flat_map(|sample| sample.to_le_bytes())
+            .collect();
+        let desc = FormatDesc::planar(1, 1, 10);
+        let planes = [
+            Some((&luma[..], 16)),
+            Some((&chroma[..], 8)),
+            Some((&chroma[..], 8)),
+        ];
+
+        let [luma, blue, _] = desc.samplers(planes, (0, 0, 8, 1)).unwrap();
+        // Rounded to nearest rather than truncated, and 1021 & up would round to 256 without
+        // the clamp
+        assert_eq!(read(&luma), [[0, 0, 1, 1, 2, 128, 255, 255]]);
+        assert_eq!(read(&blue), [[75, 255, 1, 1]]);
+
+        // Cropping offsets by whole samples, not bytes
+        let [luma, _, _] = desc.samplers(planes, (5, 0, 3, 1)).unwrap();
+        assert_eq!(read(&luma), [[128, 255, 255]]);
+    }
+
+    #[test]
+    fn chroma_window_rounds_outwards() {
+        let desc = FormatDesc::planar(1, 1, 8);
+        assert_eq!(desc.chroma_window((0, 0, 480, 240)), (0, 0, 240, 120));
+        assert_eq!(desc.chroma_window((1, 1, 2, 2)), (0, 0, 2, 2));
+        assert_eq!(
+            desc.chroma_window((401, 241, 479, 239)),
+            (200, 120, 240, 120)
+        );
+    }
+
+    /// Chroma sample (relative to the chroma window) that each output pixel along `axis` is
+    /// centered on, as the bilinear filter sees it
+    fn positions(axis: Axis) -> Vec<f64> {
+        let half = I48F16::ONE / 2;
+        (0..axis.dst_len)
+            .map(|i| ((I48F16::from_num(i) + half) * axis.scale - half + axis.offset).to_num())
+            .collect()
+    }
+
+    /// Chroma positions under luma samples 0..4 of a 4x4 window scaled 1:1
+    fn sited(desc: FormatDesc, location: ChromaLocation) -> (Vec<f64>, Vec<f64>) {
+        let (horizontal, vertical) = desc.chroma_axes(location, (0, 0, 4, 4), (4, 4));
+        (positions(horizontal), positions(vertical))
+    }
+
+    #[test]
+    fn chroma_siting_420() {
+        let desc = FormatDesc::planar(1, 1, 8);
+        let on_first = [0.0, 0.5, 1.0, 1.5];
+        let centered = [-0.25, 0.25, 0.75, 1.25];
+
+        assert_eq!(
+            sited(desc, ChromaLocation::Left),
+            (on_first.to_vec(), centered.to_vec())
+        );
+        assert_eq!(
+            sited(desc, ChromaLocation::Center),
+            (centered.to_vec(), centered.to_vec())
+        );
+        assert_eq!(
+            sited(desc, ChromaLocation::TopLeft),
+            (on_first.to_vec(), on_first.to_vec())
+        );
+        assert_eq!(
+            sited(desc, ChromaLocation::Top),
+            (centered.to_vec(), on_first.to_vec())
+        );
+        assert_eq!(sited(desc, ChromaLocation::Bottom).1, [-0.5, 0.0, 0.5, 1.0]);
+        // What untagged video gets
+        assert_eq!(ChromaLocation::default(), ChromaLocation::Left);
+    }
+
+    #[test]
+    fn chroma_siting_422() {
+        // Chroma rows line up with luma rows, wherever the tag says they sit
+        let desc = FormatDesc::planar(1, 0, 8);
+        let on_first = [0.0, 0.5, 1.0, 1.5];
+        let centered = [-0.25, 0.25, 0.75, 1.25];
+        let rows = [0.0, 1.0, 2.0, 3.0];
+
+        assert_eq!(
+            sited(desc, ChromaLocation::Left),
+            (on_first.to_vec(), rows.to_vec())
+        );
+        assert_eq!(
+            sited(desc, ChromaLocation::Center),
+            (centered.to_vec(), rows.to_vec())
+        );
+        assert_eq!(
+            sited(desc, ChromaLocation::TopLeft),
+            (on_first.to_vec(), rows.to_vec())
+        );
+        assert_eq!(sited(desc, ChromaLocation::BottomLeft).1, rows);
+    }
+
+    #[test]
+    fn chroma_siting_cropped_and_scaled() {
+        let desc = FormatDesc::planar(1, 1, 8);
+
+        // Luma column 2 is chroma column 1, the first in the cropped window
+        let (horizontal, _) = desc.chroma_axes(ChromaLocation::Left, (2, 0, 4, 4), (4, 4));
+        assert_eq!(positions(horizontal), [0.0, 0.5, 1.0, 1.5]);
+
+        // Halving lands output pixels between luma samples 0 & 1, and 2 & 3
+        let (horizontal, vertical) = desc.chroma_axes(ChromaLocation::Center, (0, 0, 4, 4), (2, 2));
+        assert_eq!(positions(horizontal), [0.0, 1.0]);
+        assert_eq!(positions(vertical), [0.0, 1.0]);
+    }
+}
@@ -0,0 +1,442 @@
+//! One video stream of a file: demuxing, decoding, frame-accurate seeking & looping
+
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use vexide::{fs, io::println};
+
+use crate::{
+    config::{Config, LoopMode},
+    error::PlayerError,
+    ffmpeg,
+    keyframes::{self, KeyframeIndex},
+    media::{Codec, Decoder, FormatInput, Frame, InputOptions, Packet, Receive},
+    quality::{self, Discard},
+    scheduler::{duration_to_timestamp, timestamp_to_duration},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Decoding,
+    /// Hit the end of the file, now emptying out the decoder
+    Draining,
+    /// Every frame's been handed out; only a seek gets things going again
+    Finished,
+    /// Emptying out the decoder before swapping it for one with a different `lowres`. The keyframe
+    /// to start the new one on waits in `packet`
+    Reopening,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Repeat {
+    #[default]
+    Off,
+    /// The whole file, forever
+    File,
+    /// A-B repeat between two stream times
+    Segment { start: Duration, end: Duration },
+}
+
+impl Repeat {
+    /// What `config` asks for, before anyone touches the controller
+    pub fn from_config(config: &Config) -> Self {
+        match (config.segment, config.loop_mode) {
+            (Some((start, end)), _) => Self::Segment { start, end },
+            (None, LoopMode::File) => Self::File,
+            (None, _) => Self::Off,
+        }
+    }
+}
+
+/// From this speed (in percent) up, frames nothing references aren't decoded at all; most of them
+/// would only get dropped for being late anyway
+const SKIP_NON_REFERENCE_RATE: u32 = 200;
+
+pub struct Player {
+    input: FormatInput,
+    codec: Codec,
+    decoder: Decoder,
+    packet: Packet,
+    stream_index: usize,
+    time_base: ffmpeg::AVRational,
+    keyframes: Option<KeyframeIndex>,
+    /// Frames before this are decoded but thrown away, to land exactly on a seek target
+    skip_until: Option<Duration>,
+    /// Stream time of the latest frame handed out
+    position: Duration,
+    state: State,
+    repeat: Repeat,
+    /// Added to stream time to get a timeline that keeps going across loops
+    loop_offset: Duration,
+    /// Where on that timeline the first frame after looping back belongs
+    loop_resume: Option<Duration>,
+    /// Whether a frame has been handed out since the last loop; stops an empty segment from
+    /// looping forever
+    shown_since_loop: bool,
+    /// Gap between the last two frames, i.e. how long the final frame before a loop stays up
+    frame_interval: Duration,
+    /// Frames skipped for playing fast, on top of whatever `quality` skips
+    rate_skip: ffmpeg::AVDiscard,
+    quality: quality::Level,
+    /// `lowres` for the decoder to be reopened with at the next keyframe
+    pending_lowres: Option<u8>,
+}
+
+impl Player {
+    pub fn open(path: &str, config: &Config) -> Result<Self, PlayerError> {
+        let file = fs::File::open(path)?;
+        let file_size = file.metadata()?.len();
+        println!("Opened file");
+
+        let mut input = FormatInput::open(
+            file,
+            InputOptions {
+                buffer_size: config.buffer_size,
+                debug: config.format_debug,
+                probe_size: config.probe_size,
+            },
+        )?;
+
+        for (track, index) in input.video_streams().into_iter().enumerate() {
+            let title = input.stream_title(index).unwrap_or("untitled");
+            println!("Video track {track}: stream {index}, {title}");
+        }
+
+        let (stream_index, codec) = input.video_stream(&config.track)?;
+        input.discard_all_but(stream_index);
+        let stream = input
+            .stream(stream_index)
+            .expect("stream index out of range");
+        let time_base = stream.time_base;
+        println!("Playing stream {stream_index} ({})", codec.name());
+
+        let frame_rate = stream.avg_frame_rate;
+        let frame_interval = if frame_rate.num > 0 && frame_rate.den > 0 {
+            Duration::from_secs(frame_rate.den as u64) / frame_rate.num as u32
+        } else {
+            Duration::from_secs(1) / 30
+        };
+
+        let decoder = Decoder::open(codec, stream, 0)?;
+        let mut packet = Packet::new()?;
+
+        let keyframes = match file_size {
+            Some(file_size) if config.keyframe_index => {
+                let source = keyframes::Source {
+                    file_size,
+                    stream_index,
+                    time_base: (time_base.num, time_base.den),
+                };
+                Some(load_keyframes(path, source, &mut input, &mut packet)?)
+            }
+            _ => None,
+        };
+
+        Ok(Self {
+            input,
+            codec,
+            decoder,
+            packet,
+            stream_index,
+            time_base,
+            keyframes,
+            skip_until: None,
+            position: Duration::ZERO,
+            state: State::Decoding,
+            repeat: Repeat::from_config(config),
+            loop_offset: Duration::ZERO,
+            loop_resume: None,
+            shown_since_loop: false,
+            frame_interval,
+            rate_skip: ffmpeg::AVDISCARD_DEFAULT,
+            quality: quality::Level::Full,
+            pending_lowres: None,
+        })
+    }
+
+    pub fn stream(&self) -> &ffmpeg::AVStream {
+        self.input
+            .stream(self.stream_index)
+            .expect("stream index out of range")
+    }
+
+    /// Length of the whole file, if the container says
+    pub fn duration(&self) -> Option<Duration> {
+        self.input.duration()
+    }
+
+    /// Stream time of the latest frame from [`Self::next_frame`]
+    pub fn position(&self) -> Duration {
+        self.position
+    }
+
+    pub fn repeat(&self) -> Repeat {
+        self.repeat
+    }
+
+    /// Takes effect from the next frame; a segment that's already behind us loops right away
+    pub fn set_repeat(&mut self, repeat: Repeat) {
+        self.repeat = repeat;
+    }
+
+    /// Gap between the last two frames
+    pub fn frame_interval(&self) -> Duration {
+        self.frame_interval
+    }
+
+    /// Let the decoder skip frames when playing at `percent` of realtime is too fast for it
+    pub fn set_rate(&mut self, percent: u32) {
+        self.rate_skip = if percent >= SKIP_NON_REFERENCE_RATE {
+            ffmpeg::AVDISCARD_NONREF
+        } else {
+            ffmpeg::AVDISCARD_DEFAULT
+        };
+        self.apply_skips();
+    }
+
+    /// Lowest quality the decoder can go down to
+    pub fn max_quality_level(&self) -> quality::Level {
+        if self.codec.max_lowres() > 0 {
+            quality::Level::LowRes
+        } else {
+            quality::Level::SkipNonRef
+        }
+    }
+
+    /// Let the decoder cut corners to keep up; applies from the next packet, or for a change of
+    /// resolution from the next keyframe
+    pub fn set_quality(&mut self, level: quality::Level) {
+        self.quality = level;
+        self.apply_skips();
+        self.pending_lowres = Some(level.lowres()).filter(|&lowres| lowres != self.lowres());
+    }
+
+    /// Power of two the frames from [`Self::next_frame`] are scaled down by
+    pub fn lowres(&self) -> u8 {
+        self.decoder.lowres()
+    }
+
+    fn apply_skips(&mut self) {
+        let level = self.quality;
+        // AVDiscard values go up with how much gets skipped
+        self.decoder
+            .set_skip_frame(self.rate_skip.max(av_discard(level.skip_frame())));
+        self.decoder
+            .set_skip_loop_filter(av_discard(level.skip_loop_filter()));
+        self.decoder.set_skip_idct(av_discard(level.skip_idct()));
+    }
+
+    /// Where `frame` goes on a timeline that carries on across loops, for scheduling. Only differs
+    /// from [`Self::frame_time`] once the player has looped
+    pub fn presentation_time(&self, frame: &Frame) -> Option<Duration> {
+        self.frame_time(frame).map(|time| time + self.loop_offset)
+    }
+
+    /// Stream time `frame` is meant to be shown at
+    pub fn frame_time(&self, frame: &Frame) -> Option<Duration> {
+        timestamp_to_duration(
+            frame.best_effort_timestamp(),
+            self.time_base.num,
+            self.time_base.den,
+        )
+    }
+
+    /// Decode the next frame to show into `frame`. Returns `false` once every frame has been
+    /// handed out, including the ones the decoder was still holding back at end of file, and keeps
+    /// doing so until the next seek
+    pub fn next_frame(&mut self, frame: &mut Frame) -> Result<bool, PlayerError> {
+        if self.state == State::Finished {
+            return Ok(false);
+        }
+
+        loop {
+            match self.decoder.receive_frame(frame)? {
+                Receive::Frame => {
+                    let time = self.frame_time(frame);
+                    if let (Some(target), Some(time)) = (self.skip_until, time)
+                        && time < target
+                    {
+                        continue;
+                    }
+                    if let (Repeat::Segment { end, .. }, Some(time)) = (self.repeat, time)
+                        && time >= end
+                        && self.shown_since_loop
+                    {
+                        self.loop_back()?;
+                        continue;
+                    }
+
+                    if let Some(time) = time {
+                        if let Some(resume) = self.loop_resume.take() {
+                            self.loop_offset = resume.saturating_sub(time);
+                        } else if self.shown_since_loop
+                            && self.skip_until.is_none()
+                            && time > self.position
+                        {
+                            self.frame_interval = time - self.position;
+                        }
+                        self.position = time;
+                    }
+                    self.skip_until = None;
+                    self.shown_since_loop = true;
+                    return Ok(true);
+                }
+                Receive::Drained if self.state == State::Reopening => {
+                    self.reopen_decoder()?;
+                    self.decoder.send_packet(&self.packet)?;
+                    self.state = State::Decoding;
+                    continue;
+                }
+                Receive::Drained if self.repeat != Repeat::Off && self.shown_since_loop => {
+                    self.loop_back()?;
+                }
+                Receive::Drained => {
+                    self.state = State::Finished;
+                    return Ok(false);
+                }
+                Receive::NeedsInput => {}
+            }
+
+            if self.input.read_packet(&mut self.packet)? {
+                // Discarded streams can still let the odd packet through
+                if self.packet.stream_index() == self.stream_index {
+                    if self.pending_lowres.is_some() && self.packet.is_keyframe() {
+                        // Let the old decoder hand out the frames it's holding back before
+                        // switching
+                        self.decoder.send_eof()?;
+                        self.state = State::Reopening;
+                    } else {
+                        self.decoder.send_packet(&self.packet)?;
+                    }
+                }
+            } else {
+                // Flush out delayed frames (B-frame reordering, frame threading, ...)
+                self.decoder.send_eof()?;
+                self.state = State::Draining;
+            }
+        }
+    }
+
+    /// Make `target` the next frame [`Self::next_frame`] hands out: seek to the keyframe before it,
+    /// then decode forward to it
+    pub fn seek_to(&mut self, target: Duration) -> Result<(), PlayerError> {
+        let timestamp = duration_to_timestamp(target, self.time_base.num, self.time_base.den);
+        let current = duration_to_timestamp(self.position, self.time_base.num, self.time_base.den);
+
+        let keyframe = match &self.keyframes {
+            Some(index)
+                if self.state == State::Decoding && index.same_group(current, timestamp) =>
+            {
+                // Decoding on from here is quicker than going back to the keyframe
+                self.skip_until = Some(target);
+                return Ok(());
+            }
+            Some(index) => index.at_or_before(timestamp),
+            None => None,
+        };
+
+        self.input.seek(
+            self.stream_index,
+            keyframe.map_or(timestamp, |keyframe| keyframe.timestamp),
+        )?;
+        if self.pending_lowres.is_some() {
+            // Seeking lands on a keyframe anyway, so no need to wait for one
+            self.reopen_decoder()?;
+        } else {
+            self.decoder.flush();
+        }
+        self.skip_until = Some(target);
+        self.state = State::Decoding;
+        Ok(())
+    }
+
+    /// Swap in a decoder with the pending `lowres`, keeping the current skip settings
+    fn reopen_decoder(&mut self) -> Result<(), PlayerError> {
+        let lowres = self.pending_lowres.take().unwrap_or(self.lowres());
+        let stream = self
+            .input
+            .stream(self.stream_index)
+            .expect("stream index out of range");
+        self.decoder = Decoder::open(self.codec, stream, lowres)?;
+        self.apply_skips();
+        println!("Reopened decoder at 1/{} size", 1 << lowres);
+        Ok(())
+    }
+
+    /// Go back to the start of the file/segment without reopening anything. The timeline carries
+    /// on from the last frame, so the scheduler doesn't notice
+    fn loop_back(&mut self) -> Result<(), PlayerError> {
+        let start = match self.repeat {
+            Repeat::Segment { start, .. } => start,
+            _ => Duration::ZERO,
+        };
+        println!("Looping back to {start:?}");
+
+        self.loop_resume = Some(self.position + self.loop_offset + self.frame_interval);
+        self.shown_since_loop = false;
+        self.seek_to(start)
+    }
+}
+
+fn av_discard(discard: Discard) -> ffmpeg::AVDiscard {
+    match discard {
+        Discard::Default => ffmpeg::AVDISCARD_DEFAULT,
+        Discard::NonRef => ffmpeg::AVDISCARD_NONREF,
+        Discard::All => ffmpeg::AVDISCARD_ALL,
+    }
+}
+
+/// Keyframes from the sidecar next to `path`, or the container's own index, or failing both a
+/// scan through the whole file (which then gets cached to the sidecar)
+fn load_keyframes(
+    path: &str,
+    source: keyframes::Source,
+    input: &mut FormatInput,
+    packet: &mut Packet,
+) -> Result<KeyframeIndex, PlayerError> {
+    let sidecar = keyframes::sidecar_path(path);
+    if let Ok(text) = fs::read_to_string(&sidecar)
+        && let Some(index) = KeyframeIndex::from_sidecar(&text, source)
+    {
+        println!(
+            "Loaded {} keyframes from {sidecar}",
+            index.keyframes().len()
+        );
+        input.add_index_entries(source.stream_index, index.keyframes());
+        return Ok(index);
+    }
+
+    let index = KeyframeIndex::new(input.index_entries(source.stream_index));
+    if !index.is_empty() {
+        return Ok(index);
+    }
+
+    println!("No keyframe index, scanning {path}");
+    let mut found = Vec::new();
+    while input.read_packet(packet)? {
+        // Keyframes without a timestamp can't be seeked to anyway
+        if packet.stream_index() == source.stream_index
+            && packet.is_keyframe()
+            && packet.timestamp() != i64::MIN
+        {
+            found.push(keyframes::Keyframe {
+                timestamp: packet.timestamp(),
+                position: packet.position(),
+            });
+        }
+    }
+    let index = KeyframeIndex::new(found);
+    println!("Found {} keyframes", index.keyframes().len());
+
+    input.add_index_entries(source.stream_index, index.keyframes());
+    let start = index
+        .keyframes()
+        .first()
+        .map_or(0, |keyframe| keyframe.timestamp);
+    input.seek(source.stream_index, start)?;
+
+    if let Err(err) = fs::write(&sidecar, index.to_sidecar(source)) {
+        println!("Couldn't cache keyframe index: {err}");
+    }
+    Ok(index)
+}
@@ -0,0 +1,282 @@
+//! Ordered list of files to play, from a whole directory or an `.m3u` playlist
+
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+
+pub struct Playlist {
+    entries: Vec<String>,
+    /// Play order, as indices into `entries`
+    order: Vec<usize>,
+    position: usize,
+    looping: bool,
+    shuffle: Option<Rng>,
+}
+
+impl Playlist {
+    pub fn new(entries: Vec<String>) -> Self {
+        Self {
+            order: (0..entries.len()).collect(),
+            entries,
+            position: 0,
+            looping: false,
+            shuffle: None,
+        }
+    }
+
+    /// Start over from the top instead of stopping after the last entry
+    pub fn looping(mut self, looping: bool) -> Self {
+        self.looping = looping;
+        self
+    }
+
+    /// Play in random order, reshuffled every time the list loops
+    pub fn shuffled(mut self, seed: u32) -> Self {
+        let mut rng = Rng::new(seed);
+        rng.shuffle(&mut self.order);
+        self.shuffle = Some(rng);
+        self
+    }
+
+    pub fn current(&self) -> Option<&str> {
+        let index = *self.order.get(self.position)?;
+        Some(&self.entries[index])
+    }
+
+    /// Move on to the next entry. At the end this wraps around if looping (or `wrap` is set),
+    /// otherwise returns `None`
+    pub fn advance(&mut self, wrap: bool) -> Option<&str> {
+        self.position += 1;
+        if self.position >= self.order.len() {
+            if !(self.looping || wrap) {
+                return None;
+            }
+
+            self.position = 0;
+            if let Some(rng) = &mut self.shuffle {
+                rng.shuffle(&mut self.order);
+            }
+        }
+        self.current()
+    }
+
+    /// Go back one entry, wrapping around to the last
+    pub fn back(&mut self) -> Option<&str> {
+        self.position = self
+            .position
+            .checked_sub(1)
+            .unwrap_or(self.order.len().saturating_sub(1));
+        self.current()
+    }
+}
+
+/// Entries of an `.m3u`/`.m3u8` playlist, with relative paths resolved against `base_dir`
+pub fn parse_m3u(text: &str, base_dir: &str) -> Vec<String> {
+    text.lines()
+        .map(str::trim)
+        // `#EXTM3U`, `#EXTINF` & comments
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            if line.starts_with('/') {
+                line.to_string()
+            } else {
+                join(base_dir, line)
+            }
+        })
+        .collect()
+}
+
+/// `dir/name`, or just `name` for the root directory
+pub fn join(dir: &str, name: &str) -> String {
+    let dir = dir.trim_end_matches('/');
+    if dir.is_empty() {
+        name.to_string()
+    } else {
+        alloc::format!("{dir}/{name}")
+    }
+}
+
+/// Directory part of `path`, empty if there isn't one
+pub fn parent(path: &str) -> &str {
+    path.rsplit_once('/').map_or("", |(dir, _)| dir)
+}
+
+/// Whether `name` ends in one of `extensions` (without the dot), ignoring case
+pub fn has_extension(name: &str, extensions: &[String]) -> bool {
+    name.rsplit_once('.').is_some_and(|(_, ext)| {
+        extensions
+            .iter()
+            .any(|candidate| candidate.eq_ignore_ascii_case(ext))
+    })
+}
+
+pub fn is_playlist(name: &str) -> bool {
+    has_extension(name, &["m3u".into(), "m3u8".into()])
+}
+
+/// xorshift32, plenty for shuffling
+struct Rng(u32);
+
+impl Rng {
+    fn new(seed: u32) -> Self {
+        Self(seed.max(1))
+    }
+
+    fn next(&mut self) -> u32 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.0 = x;
+        x
+    }
+
+    /// Fisher-Yates
+    fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            let j = self.next() as usize % (i + 1);
+            items.swap(i, j);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+
+    fn entries(count: usize) -> Vec<String> {
+        (0..count)
+            .map(|index| alloc::format!("{index}.mp4"))
+            .collect()
+    }
+
+    /// Every entry from the current one on, `count` times
+    fn play(playlist: &mut Playlist, count: usize) -> Vec<String> {
+        let mut played = vec![playlist.current().unwrap().to_string()];
+        for _ in 1..count {
+            played.push(playlist.advance(false).unwrap().to_string());
+        }
+        played
+    }
+
+    #[test]
+    fn m3u_entries() {
+        let text = "#EXTM3U\n\
+                    #EXTINF:123,Some title\n\
+                    intro.mp4\n\
+                    \n\
+                    # a comment\n\
+                    \x20 clips/a.mkv \n\
+                    /videos/b.webm\n";
+        assert_eq!(
+            parse_m3u(text, "lists"),
+            ["lists/intro.mp4", "lists/clips/a.mkv", "/videos/b.webm"]
+        );
+        // At the root, relative entries stay as they are
+        assert_eq!(
+            parse_m3u(text, ""),
+            ["intro.mp4", "clips/a.mkv", "/videos/b.webm"]
+        );
+    }
+
+    #[test]
+    fn m3u_crlf() {
+        assert_eq!(
+            parse_m3u("#EXTM3U\r\n#EXTINF:1,a\r\na.mp4\r\nb.mp4\r\n", "dir/"),
+            ["dir/a.mp4", "dir/b.mp4"]
+        );
+    }
+
+    #[test]
+    fn paths() {
+        assert_eq!(join("videos", "a.mp4"), "videos/a.mp4");
+        assert_eq!(join("videos/", "a.mp4"), "videos/a.mp4");
+        assert_eq!(join("", "a.mp4"), "a.mp4");
+        assert_eq!(join("/", "a.mp4"), "a.mp4");
+
+        assert_eq!(parent("videos/clips/a.mp4"), "videos/clips");
+        assert_eq!(parent("/a.mp4"), "");
+        assert_eq!(parent("a.mp4"), "");
+    }
+
+    #[test]
+    fn extensions() {
+        // Like `media::demuxer_extensions`: lowercase, no dots
+        let demuxers: Vec<String> = ["mkv", "mov", "mp4", "webm"].map(String::from).to_vec();
+        assert!(has_extension("a.mp4", &demuxers));
+        assert!(has_extension("A.MKV", &demuxers));
+        assert!(has_extension("some.file.webm", &demuxers));
+        assert!(!has_extension("a.avi", &demuxers));
+        assert!(!has_extension("mp4", &demuxers));
+        assert!(!has_extension("a.mp4.idx", &demuxers));
+        assert!(!has_extension("a.", &demuxers));
+
+        assert!(is_playlist("list.m3u"));
+        assert!(is_playlist("LIST.M3U8"));
+        assert!(!is_playlist("a.mp4"));
+    }
+
+    #[test]
+    fn in_order() {
+        let mut playlist = Playlist::new(entries(3));
+        assert_eq!(play(&mut playlist, 3), ["0.mp4", "1.mp4", "2.mp4"]);
+        assert_eq!(playlist.advance(false), None);
+
+        let mut playlist = Playlist::new(entries(3));
+        assert_eq!(playlist.back(), Some("2.mp4"));
+        assert_eq!(playlist.back(), Some("1.mp4"));
+
+        assert_eq!(Playlist::new(Vec::new()).current(), None);
+    }
+
+    #[test]
+    fn wraps_when_looping() {
+        let mut playlist = Playlist::new(entries(2)).looping(true);
+        assert_eq!(
+            play(&mut playlist, 5),
+            ["0.mp4", "1.mp4", "0.mp4", "1.mp4", "0.mp4"]
+        );
+
+        assert_eq!(playlist.back(), Some("1.mp4"));
+
+        // Skipping past the end wraps even without looping
+        let mut playlist = Playlist::new(entries(2));
+        playlist.advance(false);
+        assert_eq!(playlist.advance(true), Some("0.mp4"));
+    }
+
+    #[test]
+    fn shuffle_is_a_seeded_permutation() {
+        let order = |seed| play(&mut Playlist::new(entries(8)).shuffled(seed), 8);
+
+        let shuffled = order(42);
+        assert_eq!(shuffled, order(42));
+        assert_ne!(shuffled, order(43));
+        assert_ne!(shuffled, entries(8));
+
+        let mut sorted = shuffled.clone();
+        sorted.sort();
+        assert_eq!(sorted, entries(8));
+    }
+
+    #[test]
+    fn reshuffles_on_loop() {
+        let mut playlist = Playlist::new(entries(8)).looping(true).shuffled(7);
+        let first = play(&mut playlist, 8);
+        playlist.advance(false);
+        let second = play(&mut playlist, 8);
+        assert_ne!(first, second);
+
+        let mut sorted = second.clone();
+        sorted.sort();
+        assert_eq!(sorted, entries(8));
+
+        // Without looping it just ends
+        let mut playlist = Playlist::new(entries(8)).shuffled(7);
+        play(&mut playlist, 8);
+        assert_eq!(playlist.advance(false), None);
+    }
+}
@@ -0,0 +1,216 @@
+//! Trading picture quality for decode speed when playback falls behind
+//!
+//! Kept free of ffmpeg types like the scheduler, so the governor can be driven by made-up
+//! lateness figures.
+
+use core::time::Duration;
+
+/// How much the decoder may cut corners. Each level skips everything the one before it does
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    #[default]
+    Full,
+    /// No deblocking on frames nothing references
+    NoDeblockNonRef,
+    /// No deblocking at all
+    NoDeblock,
+    /// Also no IDCT on frames nothing references
+    NoIdctNonRef,
+    /// Also don't decode frames nothing references
+    SkipNonRef,
+    /// Also decode at half resolution, for codecs that support it. Only takes effect from the next
+    /// keyframe, since the decoder has to be reopened for it
+    LowRes,
+}
+
+/// Which frames a shortcut applies to; mirrors the `AVDiscard` values the levels use
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Discard {
+    Default,
+    NonRef,
+    All,
+}
+
+impl Level {
+    const ALL: [Level; 6] = [
+        Level::Full,
+        Level::NoDeblockNonRef,
+        Level::NoDeblock,
+        Level::NoIdctNonRef,
+        Level::SkipNonRef,
+        Level::LowRes,
+    ];
+
+    pub fn skip_loop_filter(self) -> Discard {
+        match self {
+            Level::Full => Discard::Default,
+            Level::NoDeblockNonRef => Discard::NonRef,
+            _ => Discard::All,
+        }
+    }
+
+    pub fn skip_idct(self) -> Discard {
+        if self >= Level::NoIdctNonRef {
+            Discard::NonRef
+        } else {
+            Discard::Default
+        }
+    }
+
+    pub fn skip_frame(self) -> Discard {
+        if self >= Level::SkipNonRef {
+            Discard::NonRef
+        } else {
+            Discard::Default
+        }
+    }
+
+    /// Power of two to divide the decoded size by
+    pub fn lowres(self) -> u8 {
+        (self == Level::LowRes) as u8
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Stats {
+    pub level: Level,
+    /// Lowest quality reached
+    pub worst: Level,
+    /// Times the level went down
+    pub degraded: u32,
+    /// Times it went back up
+    pub recovered: u32,
+}
+
+/// Frames later than this count against the decoder
+const LATE_THRESHOLD: Duration = Duration::from_millis(15);
+/// Late frames in a row before dropping a level
+const DEGRADE_AFTER: u32 = 3;
+/// Frames on time in a row before going back up a level; a few seconds' worth
+const RECOVER_AFTER: u32 = 90;
+
+/// Picks a [`Level`] from how late frames turn up: drops quality a step at a time while frames
+/// keep being late, and only gives it back after a good run of frames on time, so it doesn't flap
+/// between two levels
+pub struct Governor {
+    /// Lowest level the decoder supports
+    max: Level,
+    late_streak: u32,
+    on_time_streak: u32,
+    stats: Stats,
+}
+
+impl Governor {
+    pub fn new(max: Level) -> Self {
+        Self {
+            max,
+            late_streak: 0,
+            on_time_streak: 0,
+            stats: Stats::default(),
+        }
+    }
+
+    pub fn level(&self) -> Level {
+        self.stats.level
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    /// Account for a frame that was `late_by` behind its deadline (zero if it made it). Returns the
+    /// new level when it changes
+    pub fn update(&mut self, late_by: Duration) -> Option<Level> {
+        if late_by > LATE_THRESHOLD {
+            self.late_streak += 1;
+            self.on_time_streak = 0;
+        } else if late_by.is_zero() {
+            self.on_time_streak += 1;
+            self.late_streak = 0;
+        } else {
+            // Slightly late; not worth acting on either way
+            self.late_streak = 0;
+            self.on_time_streak = 0;
+        }
+
+        let index = self.level().index();
+        let level = if self.late_streak >= DEGRADE_AFTER && self.level() < self.max {
+            self.stats.degraded += 1;
+            Level::ALL[index + 1]
+        } else if self.on_time_streak >= RECOVER_AFTER && self.level() > Level::Full {
+            self.stats.recovered += 1;
+            Level::ALL[index - 1]
+        } else {
+            return None;
+        };
+
+        self.late_streak = 0;
+        self.on_time_streak = 0;
+        self.stats.level = level;
+        self.stats.worst = self.stats.worst.max(level);
+        Some(level)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LATE: Duration = Duration::from_millis(40);
+
+    #[test]
+    fn degrades_down_to_max() {
+        let mut governor = Governor::new(Level::LowRes);
+        let mut changes = 0;
+        for _ in 0..100 {
+            changes += governor.update(LATE).is_some() as u32;
+        }
+        assert_eq!(governor.level(), Level::LowRes);
+        assert_eq!(changes, Level::ALL.len() as u32 - 1);
+
+        for _ in 0..RECOVER_AFTER - 1 {
+            assert_eq!(governor.update(Duration::ZERO), None);
+        }
+        assert_eq!(governor.update(Duration::ZERO), Some(Level::SkipNonRef));
+
+        let stats = governor.stats();
+        assert_eq!(
+            (stats.worst, stats.degraded, stats.recovered),
+            (Level::LowRes, 5, 1)
+        );
+    }
+
+    #[test]
+    fn stops_at_max() {
+        // Codecs without lowres stop short of it
+        let mut governor = Governor::new(Level::SkipNonRef);
+        for _ in 0..100 {
+            governor.update(LATE);
+        }
+        assert_eq!(governor.level(), Level::SkipNonRef);
+        assert_eq!(governor.stats().degraded, 4);
+    }
+
+    #[test]
+    fn only_lowres_halves() {
+        for level in Level::ALL {
+            assert_eq!(level.lowres(), (level == Level::LowRes) as u8);
+        }
+    }
+
+    #[test]
+    fn ignores_slightly_late() {
+        let mut governor = Governor::new(Level::LowRes);
+        for _ in 0..10 {
+            governor.update(LATE);
+            governor.update(LATE);
+            // Breaks the streak without counting as on time
+            governor.update(Duration::from_millis(5));
+        }
+        assert_eq!(governor.level(), Level::Full);
+    }
+}
@@ -0,0 +1,174 @@
+//! Bounded queue of converted frames between the decoder and the display
+//!
+//! A fixed set of buffers cycles between `free` (waiting to be converted into) and `ready`
+//! (converted, waiting for their presentation time), so nothing gets allocated per frame. Whoever
+//! takes a buffer out owns it until handing it back, e.g. the one currently on screen.
+
+use alloc::{collections::VecDeque, vec::Vec};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct QueueStats {
+    /// Buffers in total
+    pub capacity: usize,
+    /// Most frames ever waiting at once
+    pub peak: usize,
+    /// Frames waiting, summed over every [`FrameQueue::pop`] that got one
+    pub occupancy_total: u64,
+    pub pops: u64,
+    /// Times the display wanted a frame and none was ready
+    pub underruns: u64,
+    /// Times the decoder found every buffer taken and had to wait, once per wait
+    pub overruns: u64,
+}
+
+impl QueueStats {
+    /// Frames waiting on average when one gets taken, in hundredths
+    pub fn average_occupancy(&self) -> u64 {
+        self.occupancy_total * 100 / self.pops.max(1)
+    }
+}
+
+pub struct FrameQueue<T> {
+    /// Oldest first
+    ready: VecDeque<T>,
+    free: Vec<T>,
+    /// Whether the last pop came up empty, so one stall only counts as one underrun
+    starved: bool,
+    /// Whether the last [`Self::take_free`] came up empty, likewise for overruns
+    full: bool,
+    stats: QueueStats,
+}
+
+impl<T> FrameQueue<T> {
+    pub fn new(buffers: Vec<T>) -> Self {
+        Self {
+            ready: VecDeque::with_capacity(buffers.len()),
+            stats: QueueStats {
+                capacity: buffers.len(),
+                ..QueueStats::default()
+            },
+            free: buffers,
+            // Nothing's expected before the first frame is in
+            starved: true,
+            full: false,
+        }
+    }
+
+    /// Buffer to convert the next frame into; `None` while they're all taken
+    pub fn take_free(&mut self) -> Option<T> {
+        let buffer = self.free.pop();
+        if buffer.is_none() && !self.full {
+            self.stats.overruns += 1;
+        }
+        self.full = buffer.is_none();
+        buffer
+    }
+
+    /// Queue up a converted frame
+    pub fn push(&mut self, frame: T) {
+        self.ready.push_back(frame);
+        self.stats.peak = self.stats.peak.max(self.ready.len());
+    }
+
+    /// Oldest converted frame
+    pub fn pop(&mut self) -> Option<T> {
+        let Some(frame) = self.ready.pop_front() else {
+            if !self.starved {
+                self.starved = true;
+                self.stats.underruns += 1;
+            }
+            return None;
+        };
+
+        self.starved = false;
+        self.stats.pops += 1;
+        self.stats.occupancy_total += self.ready.len() as u64 + 1;
+        Some(frame)
+    }
+
+    /// Hand a buffer back once it's been shown (or turned out not to be needed)
+    pub fn recycle(&mut self, buffer: T) {
+        self.free.push(buffer);
+    }
+
+    /// Throw away every waiting frame, e.g. after seeking
+    pub fn clear(&mut self) {
+        self.free.extend(self.ready.drain(..));
+        // Refilling after this isn't the decoder falling behind
+        self.starved = true;
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+
+    #[test]
+    fn cycles_buffers() {
+        let mut queue = FrameQueue::new(vec![1, 2, 3]);
+        let first = queue.take_free().unwrap();
+        let second = queue.take_free().unwrap();
+        queue.push(first);
+        queue.push(second);
+        assert_eq!(queue.pop(), Some(first));
+        queue.recycle(first);
+        assert_eq!(queue.pop(), Some(second));
+
+        let stats = queue.stats();
+        assert_eq!((stats.capacity, stats.peak, stats.pops), (3, 2, 2));
+        // 2 waiting at the first pop, 1 at the second
+        assert_eq!(stats.average_occupancy(), 150);
+    }
+
+    #[test]
+    fn one_overrun_per_full_stretch() {
+        let mut queue = FrameQueue::new(vec![1, 2]);
+        let first = queue.take_free().unwrap();
+        let second = queue.take_free().unwrap();
+        queue.push(first);
+        queue.push(second);
+
+        // The decoder polls over & over while the queue is full
+        for _ in 0..100 {
+            assert_eq!(queue.take_free(), None);
+        }
+        assert_eq!(queue.stats().overruns, 1);
+
+        let shown = queue.pop().unwrap();
+        queue.recycle(shown);
+        let frame = queue.take_free().unwrap();
+        queue.push(frame);
+        assert_eq!(queue.take_free(), None);
+        assert_eq!(queue.take_free(), None);
+        assert_eq!(queue.stats().overruns, 2);
+    }
+
+    #[test]
+    fn one_underrun_per_stall() {
+        let mut queue = FrameQueue::new(vec![1, 2]);
+        // Waiting on the first frame doesn't count
+        assert_eq!(queue.pop(), None);
+        assert_eq!(queue.stats().underruns, 0);
+
+        let frame = queue.take_free().unwrap();
+        queue.push(frame);
+        queue.pop().unwrap();
+        for _ in 0..100 {
+            assert_eq!(queue.pop(), None);
+        }
+        assert_eq!(queue.stats().underruns, 1);
+
+        // Nor does refilling after a seek
+        let frame = queue.take_free().unwrap();
+        queue.push(frame);
+        queue.clear();
+        assert_eq!(queue.pop(), None);
+        assert_eq!(queue.stats().underruns, 1);
+    }
+}
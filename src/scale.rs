@@ -0,0 +1,509 @@
+//! Separable plane scaler with precomputed fixed-point coefficient tables
+//!
+//! Each 8-bit plane is scaled horizontally one source row at a time into a small row cache (kept as
+//! `U8F6`, i.e. pixel << 6, so the vertical pass doesn't lose precision), then rows are blended
+//! vertically on demand by whoever consumes the output (see [`crate::render`]). The vertical blend
+//! is the contiguous part, so that's what gets the NEON treatment on the Brain.
+
+use alloc::{vec, vec::Vec};
+
+use fixed::types::{I48F16, U2F14};
+
+/// Filter weight; weights for one output pixel always sum to exactly 1.0
+pub type Weight = U2F14;
+
+const WEIGHT_ONE: u16 = 1 << Weight::FRAC_NBITS;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ScaleFilter {
+    Nearest,
+    #[default]
+    Bilinear,
+    /// Box filter, averages every source pixel covered by the output pixel
+    Area,
+}
+
+/// How output pixels map onto one source axis
+///
+/// Output pixel `i` is centered on source position `(i + 0.5) * scale - 0.5 + offset`, where
+/// source pixel `n` is centered on `n`. Anything outside `0..src_len` is clamped to the edges.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Axis {
+    pub src_len: usize,
+    pub dst_len: usize,
+    pub scale: I48F16,
+    pub offset: I48F16,
+}
+
+impl Axis {
+    /// Stretch `src_len` pixels evenly over `dst_len`
+    pub fn new(src_len: usize, dst_len: usize) -> Self {
+        Self {
+            src_len,
+            dst_len,
+            scale: I48F16::from_num(src_len) / I48F16::from_num(dst_len.max(1)),
+            offset: I48F16::ZERO,
+        }
+    }
+}
+
+/// Source window & weights for every output pixel along one axis
+#[derive(Debug, Clone)]
+pub struct Coefficients {
+    taps: usize,
+    starts: Vec<u32>,
+    weights: Vec<Weight>,
+}
+
+impl Coefficients {
+    pub fn new(filter: ScaleFilter, axis: Axis) -> Self {
+        let Axis {
+            src_len,
+            dst_len,
+            scale,
+            offset,
+        } = axis;
+        assert!(src_len > 0 && dst_len > 0, "Can't scale an empty axis");
+
+        let taps = match filter {
+            ScaleFilter::Nearest => 1,
+            ScaleFilter::Bilinear => 2,
+            ScaleFilter::Area => scale.ceil().to_num::<usize>() + 1,
+        }
+        .min(src_len);
+
+        let mut coefficients = Self {
+            taps,
+            starts: Vec::with_capacity(dst_len),
+            weights: Vec::with_capacity(dst_len * taps),
+        };
+
+        // (source position, weight) pairs for the current output pixel
+        let mut window: Vec<(i64, Weight)> = Vec::with_capacity(taps + 1);
+        let half = I48F16::from_bits(1 << 15);
+        for i in 0..dst_len {
+            window.clear();
+
+            let i = I48F16::from_num(i);
+            match filter {
+                ScaleFilter::Nearest => {
+                    let center = (i + half) * scale + offset;
+                    window.push((center.to_num(), Weight::ONE));
+                }
+                ScaleFilter::Bilinear => {
+                    let center = (i + half) * scale - half + offset;
+                    let weight = Weight::from_num(center.frac());
+                    window.push((center.floor().to_num(), Weight::ONE - weight));
+                    window.push((center.floor().to_num::<i64>() + 1, weight));
+                }
+                ScaleFilter::Area => {
+                    let begin = i * scale + offset;
+                    let end = begin + scale;
+                    let mut pos = begin.floor();
+                    while pos < end {
+                        let covered = end.min(pos + I48F16::ONE) - begin.max(pos);
+                        window.push((pos.to_num(), Weight::from_num(covered / scale)));
+                        pos += I48F16::ONE;
+                    }
+                }
+            }
+
+            coefficients.push(&window, src_len);
+        }
+
+        coefficients
+    }
+
+    /// Clamp `window` onto the source axis & append it as a `taps` wide entry
+    fn push(&mut self, window: &[(i64, Weight)], src_len: usize) {
+        let last = src_len as i64 - 1;
+        let first = window
+            .iter()
+            .filter(|(_, weight)| *weight != Weight::ZERO)
+            .map(|&(pos, _)| pos.clamp(0, last))
+            .min()
+            .unwrap_or(0);
+        let start = first.min(src_len as i64 - self.taps as i64) as usize;
+
+        let offset = self.weights.len();
+        self.weights.resize(offset + self.taps, Weight::ZERO);
+        let weights = &mut self.weights[offset..];
+
+        for &(pos, weight) in window.iter().filter(|(_, weight)| *weight != Weight::ZERO) {
+            let tap = (pos.clamp(0, last) as usize - start).min(self.taps - 1);
+            weights[tap] += weight;
+        }
+
+        // Hand any rounding error to the heaviest tap, so flat areas stay flat
+        let total: u16 = weights.iter().map(|weight| weight.to_bits()).sum();
+        let heaviest = (0..self.taps).max_by_key(|&tap| weights[tap]).unwrap_or(0);
+        weights[heaviest] = Weight::from_bits(
+            (weights[heaviest].to_bits() as i32 + WEIGHT_ONE as i32 - total as i32) as u16,
+        );
+
+        self.starts.push(start as u32);
+    }
+
+    pub fn taps(&self) -> usize {
+        self.taps
+    }
+
+    pub fn len(&self) -> usize {
+        self.starts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.starts.is_empty()
+    }
+
+    /// First source index & per-tap weights for output pixel `index`
+    pub fn get(&self, index: usize) -> (usize, &[Weight]) {
+        let weights = &self.weights[index * self.taps..(index + 1) * self.taps];
+        (self.starts[index] as usize, weights)
+    }
+}
+
+/// Anything that can hand out rows of 8-bit samples
+pub trait RowSource {
+    /// Row `y`, at least as wide as the scaler's source width. `scratch` is there for sources that
+    /// need to unpack first; plain planes can just return a slice of themselves
+    fn row<'s>(&'s self, y: usize, scratch: &'s mut [u8]) -> &'s [u8];
+}
+
+/// Scales single 8-bit planes between two fixed resolutions
+pub struct Scaler {
+    filter: ScaleFilter,
+    /// `(horizontal, vertical)` the coefficients were built from
+    axes: (Axis, Axis),
+    horizontal: Coefficients,
+    vertical: Coefficients,
+    /// Horizontally scaled source rows (`U8F6`), one slot per vertical tap
+    rows: Vec<u16>,
+    /// Which source row each slot currently holds
+    row_tags: Vec<usize>,
+    /// Unpack space for `RowSource`s
+    scratch: Vec<u8>,
+}
+
+impl Scaler {
+    pub fn new(filter: ScaleFilter, horizontal: Axis, vertical: Axis) -> Self {
+        let axes = (horizontal, vertical);
+        let horizontal = Coefficients::new(filter, horizontal);
+        let vertical = Coefficients::new(filter, vertical);
+        let slots = vertical.taps();
+
+        Self {
+            filter,
+            axes,
+            rows: vec![0; slots * axes.0.dst_len],
+            row_tags: vec![usize::MAX; slots],
+            scratch: vec![0; axes.0.src_len],
+            horizontal,
+            vertical,
+        }
+    }
+
+    /// Whether this scaler can be reused for the given configuration
+    pub fn matches(&self, filter: ScaleFilter, horizontal: Axis, vertical: Axis) -> bool {
+        self.filter == filter && self.axes == (horizontal, vertical)
+    }
+
+    pub fn dst_width(&self) -> usize {
+        self.horizontal.len()
+    }
+
+    pub fn dst_height(&self) -> usize {
+        self.vertical.len()
+    }
+
+    /// Start producing output rows for a new frame of `src`
+    pub fn frame<'a, S: RowSource>(&'a mut self, src: &'a S) -> Rows<'a, S> {
+        self.row_tags.fill(usize::MAX);
+        Rows { scaler: self, src }
+    }
+}
+
+/// Output rows of one frame, scaling source rows into the row cache on demand
+pub struct Rows<'a, S> {
+    scaler: &'a mut Scaler,
+    src: &'a S,
+}
+
+impl<S: RowSource> Rows<'_, S> {
+    /// Everything needed to blend output row `y`
+    pub fn row(&mut self, y: usize) -> RowBlend<'_> {
+        let scaler = &mut *self.scaler;
+        let width = scaler.dst_width();
+        let slots = scaler.vertical.taps();
+        let (first, weights) = scaler.vertical.get(y);
+
+        for row in first..first + weights.len() {
+            let slot = row % slots;
+            if scaler.row_tags[slot] != row {
+                let src_row = self.src.row(row, &mut scaler.scratch);
+                scale_row(
+                    &scaler.horizontal,
+                    &src_row[..scaler.axes.0.src_len],
+                    &mut scaler.rows[slot * width..][..width],
+                );
+                scaler.row_tags[slot] = row;
+            }
+        }
+
+        RowBlend {
+            rows: &scaler.rows,
+            width,
+            first,
+            slots,
+            weights,
+        }
+    }
+}
+
+/// Horizontally scaled source rows & the vertical weights that make up one output row
+pub struct RowBlend<'a> {
+    rows: &'a [u16],
+    width: usize,
+    first: usize,
+    slots: usize,
+    weights: &'a [Weight],
+}
+
+impl RowBlend<'_> {
+    /// Output pixel `x`. Scalar twin of [`Self::blend_neon`], rounding identically
+    pub fn pixel(&self, x: usize) -> u8 {
+        let acc: u32 = self
+            .weights
+            .iter()
+            .enumerate()
+            .map(|(tap, weight)| {
+                let slot = (self.first + tap) % self.slots;
+                self.rows[slot * self.width + x] as u32 * weight.to_bits() as u32
+            })
+            .sum();
+
+        // Same two-step rounding as vrshrn + vqrshrn
+        let narrowed = (acc + (1 << 15)) >> 16;
+        ((narrowed + (1 << 3)) >> 4).min(u8::MAX as u32) as u8
+    }
+
+    /// Output pixels `x..x + 8`
+    ///
+    /// # Safety
+    /// `x + 8` must not exceed the scaler's output width
+    #[cfg(target_arch = "arm")]
+    pub unsafe fn blend_neon(&self, x: usize) -> core::arch::arm::uint8x8_t {
+        use core::arch::arm::*;
+
+        unsafe {
+            let mut low = vdupq_n_u32(0);
+            let mut high = vdupq_n_u32(0);
+
+            for (tap, weight) in self.weights.iter().enumerate() {
+                let slot = (self.first + tap) % self.slots;
+                let pixels = vld1q_u16(self.rows.as_ptr().add(slot * self.width + x));
+                low = vmlal_n_u16(low, vget_low_u16(pixels), weight.to_bits());
+                high = vmlal_n_u16(high, vget_high_u16(pixels), weight.to_bits());
+            }
+
+            let narrowed = vcombine_u16(vrshrn_n_u32::<16>(low), vrshrn_n_u32::<16>(high));
+            vqrshrn_n_u16::<4>(narrowed)
+        }
+    }
+}
+
+/// Reuse the scaler in `slot` if it fits the given axes, otherwise replace it
+pub fn cached(
+    slot: &mut Option<Scaler>,
+    filter: ScaleFilter,
+    horizontal: Axis,
+    vertical: Axis,
+) -> &mut Scaler {
+    if !slot
+        .as_ref()
+        .is_some_and(|scaler| scaler.matches(filter, horizontal, vertical))
+    {
+        *slot = Some(Scaler::new(filter, horizontal, vertical));
+    }
+
+    slot.as_mut().unwrap()
+}
+
+fn scale_row(horizontal: &Coefficients, src: &[u8], out: &mut [u16]) {
+    for (x, out) in out.iter_mut().enumerate() {
+        let (start, weights) = horizontal.get(x);
+        let acc: u32 = src[start..start + weights.len()]
+            .iter()
+            .zip(weights)
+            .map(|(&pixel, weight)| pixel as u32 * weight.to_bits() as u32)
+            .sum();
+
+        // U8F14 -> U8F6
+        *out = ((acc + (1 << 7)) >> 8) as u16;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{vec, vec::Vec};
+
+    use super::*;
+
+    const FILTERS: [ScaleFilter; 3] = [
+        ScaleFilter::Nearest,
+        ScaleFilter::Bilinear,
+        ScaleFilter::Area,
+    ];
+
+    struct Plane<'a>(&'a [u8], usize);
+
+    impl RowSource for Plane<'_> {
+        fn row<'s>(&'s self, y: usize, _scratch: &'s mut [u8]) -> &'s [u8] {
+            &self.0[y * self.1..]
+        }
+    }
+
+    fn scale(filter: ScaleFilter, horizontal: Axis, vertical: Axis, src: &[u8]) -> Vec<u8> {
+        let mut scaler = Scaler::new(filter, horizontal, vertical);
+        let (width, height) = (scaler.dst_width(), scaler.dst_height());
+        let plane = Plane(src, horizontal.src_len);
+        let mut rows = scaler.frame(&plane);
+        let mut out = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let row = rows.row(y);
+            out.extend((0..width).map(|x| row.pixel(x)));
+        }
+        out
+    }
+
+    fn scale_row(filter: ScaleFilter, src: &[u8], dst_len: usize) -> Vec<u8> {
+        scale(filter, Axis::new(src.len(), dst_len), Axis::new(1, 1), src)
+    }
+
+    #[test]
+    fn weights_sum_to_one() {
+        let shifted = Axis {
+            offset: I48F16::from_num(0.25),
+            ..Axis::new(7, 3)
+        };
+        let axes = [1, 3, 7, 480, 1080]
+            .into_iter()
+            .flat_map(|src| [1, 2, 5, 240, 1920].map(|dst| Axis::new(src, dst)))
+            .chain([shifted]);
+
+        for filter in FILTERS {
+            for axis in axes.clone() {
+                let coefficients = Coefficients::new(filter, axis);
+                assert_eq!(coefficients.len(), axis.dst_len);
+                for i in 0..coefficients.len() {
+                    let (start, weights) = coefficients.get(i);
+                    let total: u16 = weights.iter().map(|weight| weight.to_bits()).sum();
+                    assert_eq!(total, WEIGHT_ONE, "{filter:?} {axis:?} pixel {i}");
+                    assert!(start + weights.len() <= axis.src_len);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn nearest() {
+        assert_eq!(
+            scale_row(ScaleFilter::Nearest, &[10, 20, 30, 40], 2),
+            [20, 40]
+        );
+        assert_eq!(
+            scale_row(ScaleFilter::Nearest, &[10, 20], 4),
+            [10, 10, 20, 20]
+        );
+    }
+
+    #[test]
+    fn bilinear() {
+        assert_eq!(
+            scale_row(ScaleFilter::Bilinear, &[10, 20, 30, 40], 2),
+            [15, 35]
+        );
+        assert_eq!(
+            scale_row(ScaleFilter::Bilinear, &[0, 200], 4),
+            [0, 50, 150, 200]
+        );
+        // Vertically too
+        let src = [0, 0, 200, 200];
+        assert_eq!(
+            scale(
+                ScaleFilter::Bilinear,
+                Axis::new(2, 1),
+                Axis::new(2, 4),
+                &src
+            ),
+            [0, 50, 150, 200]
+        );
+    }
+
+    #[test]
+    fn area() {
+        assert_eq!(scale_row(ScaleFilter::Area, &[10, 20, 30, 40], 2), [15, 35]);
+        assert_eq!(scale_row(ScaleFilter::Area, &[0, 90, 180], 2), [30, 150]);
+        assert_eq!(scale_row(ScaleFilter::Area, &[0, 200], 4), [0, 0, 200, 200]);
+        let src: Vec<u8> = (0..16).map(|i| i * 10).collect();
+        assert_eq!(
+            scale(ScaleFilter::Area, Axis::new(4, 2), Axis::new(4, 2), &src),
+            [25, 45, 105, 125]
+        );
+    }
+
+    #[test]
+    fn clamps_to_edges() {
+        let src = [10, 20, 30, 40];
+        let shifted = |offset: i32| Axis {
+            offset: I48F16::from_num(offset),
+            ..Axis::new(4, 4)
+        };
+        for filter in FILTERS {
+            let scale = |axis| scale(filter, axis, Axis::new(1, 1), &src);
+            assert_eq!(scale(shifted(-10)), [10; 4], "{filter:?}");
+            assert_eq!(scale(shifted(10)), [40; 4], "{filter:?}");
+            assert_eq!(scale(shifted(2)), [30, 40, 40, 40], "{filter:?}");
+        }
+    }
+
+    #[test]
+    fn flat_stays_flat() {
+        let sizes = [
+            (1280, 720, 480, 240),
+            (320, 180, 480, 240),
+            (7, 5, 3, 2),
+            (1, 1, 4, 4),
+            (1920, 1080, 480, 240),
+        ];
+        for filter in FILTERS {
+            for (src_width, src_height, dst_width, dst_height) in sizes {
+                for value in [0, 1, 77, 254, 255] {
+                    let src = vec![value; src_width * src_height];
+                    let scaled = scale(
+                        filter,
+                        Axis::new(src_width, dst_width),
+                        Axis::new(src_height, dst_height),
+                        &src,
+                    );
+                    assert!(
+                        scaled.iter().all(|&pixel| pixel == value),
+                        "{filter:?} {src_width}x{src_height} of {value}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn same_size_is_identity() {
+        let src: Vec<u8> = (0..64).map(|i| i * 4).collect();
+        for filter in FILTERS {
+            assert_eq!(
+                scale(filter, Axis::new(8, 8), Axis::new(8, 8), &src),
+                src,
+                "{filter:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,267 @@
+//! Presentation clock & frame pacing
+//!
+//! Kept free of any vexide/ffmpeg types so the drift & drop logic can be driven by a fake clock.
+
+use core::time::Duration;
+
+/// Monotonic time source, relative to some arbitrary epoch
+pub trait Clock {
+    fn now(&self) -> Duration;
+}
+
+/// What to do with a decoded frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    /// Frame should be shown at `deadline` (clock time)
+    Present { deadline: Duration },
+    /// Frame is too late to be worth showing; skip straight to the next one
+    Drop { late_by: Duration },
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Stats {
+    pub presented: u32,
+    pub dropped: u32,
+    /// How far behind the last scheduled frame was (negative = early)
+    pub drift_us: i64,
+}
+
+pub struct Scheduler<C: Clock> {
+    clock: C,
+    /// Clock time & (rate-scaled) pts of the first frame since the last reset
+    anchor: Option<(Duration, Duration)>,
+    /// Playback speed, in percent of realtime
+    rate: u32,
+    /// Frames later than this get dropped
+    late_threshold: Duration,
+    /// Never drop more than this many in a row, otherwise a slow decoder would never show anything
+    max_consecutive_drops: u32,
+    consecutive_drops: u32,
+    stats: Stats,
+}
+
+impl<C: Clock> Scheduler<C> {
+    pub const DEFAULT_LATE_THRESHOLD: Duration = Duration::from_millis(20);
+    pub const DEFAULT_MAX_CONSECUTIVE_DROPS: u32 = 8;
+
+    pub fn new(clock: C) -> Self {
+        Self {
+            clock,
+            anchor: None,
+            rate: 100,
+            late_threshold: Self::DEFAULT_LATE_THRESHOLD,
+            max_consecutive_drops: Self::DEFAULT_MAX_CONSECUTIVE_DROPS,
+            consecutive_drops: 0,
+            stats: Stats::default(),
+        }
+    }
+
+    pub fn with_late_threshold(mut self, threshold: Duration) -> Self {
+        self.late_threshold = threshold;
+        self
+    }
+
+    pub fn with_max_consecutive_drops(mut self, drops: u32) -> Self {
+        self.max_consecutive_drops = drops;
+        self
+    }
+
+    pub fn clock(&self) -> &C {
+        &self.clock
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    pub fn rate(&self) -> u32 {
+        self.rate
+    }
+
+    /// Change the playback speed (percent of realtime). Re-anchors on the next frame, so playback
+    /// carries on from wherever it is instead of jumping
+    pub fn set_rate(&mut self, percent: u32) {
+        self.rate = percent.max(1);
+        self.reset();
+    }
+
+    /// Forget the clock origin; the next scheduled frame will be presented immediately
+    pub fn reset(&mut self) {
+        self.anchor = None;
+        self.consecutive_drops = 0;
+    }
+
+    /// Decide what to do with a frame presented at `pts` (stream-relative)
+    pub fn schedule(&mut self, pts: Duration) -> Decision {
+        let now = self.clock.now();
+        // Stream time, stretched to wall time by the playback rate
+        let pts = pts * 100 / self.rate;
+        let (anchor_time, anchor_pts) = *self.anchor.get_or_insert((now, pts));
+        let deadline = (anchor_time + pts).saturating_sub(anchor_pts);
+
+        self.stats.drift_us = now.as_micros() as i64 - deadline.as_micros() as i64;
+
+        let late_by = now.saturating_sub(deadline);
+        if late_by > self.late_threshold && self.consecutive_drops < self.max_consecutive_drops {
+            self.consecutive_drops += 1;
+            self.stats.dropped += 1;
+            return Decision::Drop { late_by };
+        }
+
+        self.consecutive_drops = 0;
+        self.stats.presented += 1;
+        Decision::Present { deadline }
+    }
+
+    /// Time left until `deadline`, zero if it has already passed
+    pub fn time_until(&self, deadline: Duration) -> Duration {
+        deadline.saturating_sub(self.clock.now())
+    }
+}
+
+/// Inverse of [`timestamp_to_duration`], rounding down
+pub fn duration_to_timestamp(duration: Duration, num: i32, den: i32) -> i64 {
+    if num <= 0 || den <= 0 {
+        return 0;
+    }
+
+    (duration.as_nanos() as i128 * den as i128 / (num as i128 * 1_000_000_000)) as i64
+}
+
+/// Convert a timestamp in `num/den` units to a duration. `None` for `AV_NOPTS_VALUE` or negative timestamps
+pub fn timestamp_to_duration(ts: i64, num: i32, den: i32) -> Option<Duration> {
+    if ts == i64::MIN || ts < 0 || num <= 0 || den <= 0 {
+        return None;
+    }
+
+    let nanos = ts as i128 * num as i128 * 1_000_000_000 / den as i128;
+    let secs = (nanos / 1_000_000_000) as u64;
+    Some(Duration::new(secs, (nanos % 1_000_000_000) as u32))
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::Cell;
+
+    use super::*;
+
+    #[derive(Default)]
+    struct FakeClock(Cell<Duration>);
+
+    impl FakeClock {
+        fn advance(&self, millis: u64) {
+            self.0.set(self.0.get() + Duration::from_millis(millis));
+        }
+    }
+
+    impl Clock for FakeClock {
+        fn now(&self) -> Duration {
+            self.0.get()
+        }
+    }
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    fn present(millis: u64) -> Decision {
+        Decision::Present {
+            deadline: ms(millis),
+        }
+    }
+
+    #[test]
+    fn anchors_on_first_frame() {
+        let mut scheduler = Scheduler::new(FakeClock::default());
+        scheduler.clock().advance(1000);
+
+        // Whatever the first pts is, it goes up straight away and later ones are relative to it
+        assert_eq!(scheduler.schedule(ms(5000)), present(1000));
+        assert_eq!(scheduler.schedule(ms(5040)), present(1040));
+        assert_eq!(scheduler.stats().drift_us, -40_000);
+
+        scheduler.clock().advance(100);
+        scheduler.reset();
+        assert_eq!(scheduler.schedule(ms(0)), present(1100));
+        assert_eq!(scheduler.stats().drift_us, 0);
+    }
+
+    #[test]
+    fn drops_late_frames() {
+        let mut scheduler = Scheduler::new(FakeClock::default()).with_late_threshold(ms(30));
+        scheduler.schedule(ms(0));
+
+        // Within the threshold is still worth showing
+        scheduler.clock().advance(60);
+        assert_eq!(scheduler.schedule(ms(40)), present(40));
+        assert_eq!(scheduler.stats().drift_us, 20_000);
+
+        assert_eq!(
+            scheduler.schedule(ms(20)),
+            Decision::Drop { late_by: ms(40) }
+        );
+        let stats = scheduler.stats();
+        assert_eq!((stats.presented, stats.dropped), (2, 1));
+    }
+
+    #[test]
+    fn caps_consecutive_drops() {
+        let mut scheduler = Scheduler::new(FakeClock::default()).with_max_consecutive_drops(2);
+        scheduler.schedule(ms(0));
+        scheduler.clock().advance(1000);
+
+        let late = |scheduler: &mut Scheduler<FakeClock>| {
+            matches!(scheduler.schedule(ms(0)), Decision::Drop { .. })
+        };
+        assert!(late(&mut scheduler));
+        assert!(late(&mut scheduler));
+        // Shown anyway, which starts the count over
+        assert!(!late(&mut scheduler));
+        assert!(late(&mut scheduler));
+        assert_eq!(scheduler.stats().dropped, 3);
+    }
+
+    #[test]
+    fn rate_stretches_deadlines() {
+        let mut scheduler = Scheduler::new(FakeClock::default());
+        scheduler.schedule(ms(0));
+        scheduler.clock().advance(1000);
+        assert_eq!(scheduler.schedule(ms(1000)), present(1000));
+
+        // Carries on from where it is, at double speed
+        scheduler.set_rate(200);
+        assert_eq!(scheduler.rate(), 200);
+        assert_eq!(scheduler.schedule(ms(1000)), present(1000));
+        assert_eq!(scheduler.schedule(ms(2000)), present(1500));
+
+        scheduler.set_rate(50);
+        assert_eq!(scheduler.schedule(ms(2000)), present(1000));
+        assert_eq!(scheduler.schedule(ms(2100)), present(1200));
+
+        // Zero would divide by zero
+        scheduler.set_rate(0);
+        assert_eq!(scheduler.rate(), 1);
+    }
+
+    #[test]
+    fn time_until_deadline() {
+        let scheduler = Scheduler::new(FakeClock::default());
+        scheduler.clock().advance(10);
+        assert_eq!(scheduler.time_until(ms(25)), ms(15));
+        assert_eq!(scheduler.time_until(ms(5)), Duration::ZERO);
+    }
+
+    #[test]
+    fn timestamp_round_trip() {
+        assert_eq!(
+            timestamp_to_duration(1001, 1, 30000),
+            Some(Duration::from_nanos(33_366_666))
+        );
+        assert_eq!(timestamp_to_duration(i64::MIN, 1, 1000), None);
+        assert_eq!(timestamp_to_duration(-1, 1, 1000), None);
+        assert_eq!(timestamp_to_duration(1, 0, 1000), None);
+        assert_eq!(duration_to_timestamp(ms(1500), 1, 1000), 1500);
+        assert_eq!(duration_to_timestamp(ms(1500), 1, 90000), 135_000);
+        assert_eq!(duration_to_timestamp(ms(1500), 0, 1000), 0);
+    }
+}